@@ -0,0 +1,352 @@
+// Compare the leafs below two roots. Subtrees with equal hashes are skipped, so
+// diffing consecutive roots only loads the nodes on the paths that changed.
+use crate::error::{Result, TrieError};
+use crate::iter::leaves;
+use crate::leftmost_key;
+use crate::store::{
+    db::Database,
+    types::{Leaf, Node, NodeHash, Root, TrieKey},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Added(Leaf),
+    Removed(Leaf),
+    Changed { old: Leaf, new: Leaf },
+}
+
+impl Change {
+    pub fn key(&self) -> &TrieKey {
+        match self {
+            Change::Added(leaf) | Change::Removed(leaf) => &leaf.key,
+            Change::Changed { new, .. } => &new.key,
+        }
+    }
+}
+
+// the changes from old_root to new_root ordered by key
+pub fn diff(db: &mut dyn Database, old_root: Node, new_root: Node) -> Result<Vec<Change>> {
+    let old_root: Root = old_root.unwrap_as_root()?;
+    let new_root: Root = new_root.unwrap_as_root()?;
+    let mut changes: Vec<Change> = Vec::new();
+    diff_subtrees(db, old_root.left, new_root.left, None, None, &mut changes)?;
+    diff_subtrees(db, old_root.right, new_root.right, None, None, &mut changes)?;
+    Ok(changes)
+}
+
+// the split index of a node, a leaf splits after the last digit
+fn split_idx(node: &Node) -> Result<usize> {
+    match node {
+        Node::Branch(branch) => Ok(branch.key[0] as usize),
+        Node::Leaf(_) => Ok(TrieKey::BITS),
+        Node::Root(_) => Err(TrieError::InvalidChild),
+    }
+}
+
+fn children(node: Node) -> Result<(NodeHash, NodeHash)> {
+    let branch = node.unwrap_as_branch()?;
+    match (branch.left, branch.right) {
+        (Some(left), Some(right)) => Ok((left, right)),
+        _ => Err(TrieError::InvalidBranch),
+    }
+}
+
+// old_key and new_key are keys below the two subtrees that are already known,
+// passing them down avoids walking to a leaf again at every level
+fn diff_subtrees(
+    db: &mut dyn Database,
+    old_hash: Option<NodeHash>,
+    new_hash: Option<NodeHash>,
+    old_key: Option<TrieKey>,
+    new_key: Option<TrieKey>,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if old_hash == new_hash {
+        return Ok(());
+    }
+    let (old_hash, new_hash) = match (old_hash, new_hash) {
+        (Some(old_hash), Some(new_hash)) => (old_hash, new_hash),
+        (Some(old_hash), None) => return collect(db, old_hash, Change::Removed, changes),
+        (None, Some(new_hash)) => return collect(db, new_hash, Change::Added, changes),
+        (None, None) => return Ok(()),
+    };
+    let old_node: Node = db.get_node(&old_hash)?;
+    let new_node: Node = db.get_node(&new_hash)?;
+    if let (Node::Leaf(old), Node::Leaf(new)) = (&old_node, &new_node) {
+        if old.key == new.key {
+            changes.push(Change::Changed {
+                old: old.clone(),
+                new: new.clone(),
+            });
+        } else if old.key < new.key {
+            changes.push(Change::Removed(old.clone()));
+            changes.push(Change::Added(new.clone()));
+        } else {
+            changes.push(Change::Added(new.clone()));
+            changes.push(Change::Removed(old.clone()));
+        }
+        return Ok(());
+    }
+    let old_split: usize = split_idx(&old_node)?;
+    let new_split: usize = split_idx(&new_node)?;
+    if old_split == new_split {
+        let (old_left, old_right) = children(old_node.clone())?;
+        let (new_left, new_right) = children(new_node.clone())?;
+        // a shared child means that both subtrees have the same prefix
+        let (old_key, new_key) = if old_left == new_left || old_right == new_right {
+            (old_key, new_key)
+        } else {
+            let old_key: TrieKey = known_key(db, old_key, &old_node)?;
+            let new_key: TrieKey = known_key(db, new_key, &new_node)?;
+            if disjoint(
+                db, &old_key, old_hash, &new_key, new_hash, old_split, changes,
+            )? {
+                return Ok(());
+            }
+            (Some(old_key), Some(new_key))
+        };
+        let split: usize = old_split;
+        diff_subtrees(
+            db,
+            Some(old_left),
+            Some(new_left),
+            old_key.filter(|key| key.bit(split) == 0),
+            new_key.filter(|key| key.bit(split) == 0),
+            changes,
+        )?;
+        return diff_subtrees(
+            db,
+            Some(old_right),
+            Some(new_right),
+            old_key.filter(|key| key.bit(split) == 1),
+            new_key.filter(|key| key.bit(split) == 1),
+            changes,
+        );
+    }
+    let old_key: TrieKey = known_key(db, old_key, &old_node)?;
+    let new_key: TrieKey = known_key(db, new_key, &new_node)?;
+    let split: usize = old_split.min(new_split);
+    if disjoint(
+        db,
+        &old_key,
+        old_hash.clone(),
+        &new_key,
+        new_hash.clone(),
+        split,
+        changes,
+    )? {
+        return Ok(());
+    }
+    if old_split < new_split {
+        // the new subtree lies on one side of the old split
+        let (old_left, old_right) = children(old_node)?;
+        if new_key.bit(old_split) == 0 {
+            let old_key: Option<TrieKey> = Some(old_key).filter(|key| key.bit(old_split) == 0);
+            diff_subtrees(
+                db,
+                Some(old_left),
+                Some(new_hash),
+                old_key,
+                Some(new_key),
+                changes,
+            )?;
+            collect(db, old_right, Change::Removed, changes)
+        } else {
+            let old_key: Option<TrieKey> = Some(old_key).filter(|key| key.bit(old_split) == 1);
+            collect(db, old_left, Change::Removed, changes)?;
+            diff_subtrees(
+                db,
+                Some(old_right),
+                Some(new_hash),
+                old_key,
+                Some(new_key),
+                changes,
+            )
+        }
+    } else {
+        let (new_left, new_right) = children(new_node)?;
+        if old_key.bit(new_split) == 0 {
+            let new_key: Option<TrieKey> = Some(new_key).filter(|key| key.bit(new_split) == 0);
+            diff_subtrees(
+                db,
+                Some(old_hash),
+                Some(new_left),
+                Some(old_key),
+                new_key,
+                changes,
+            )?;
+            collect(db, new_right, Change::Added, changes)
+        } else {
+            let new_key: Option<TrieKey> = Some(new_key).filter(|key| key.bit(new_split) == 1);
+            collect(db, new_left, Change::Added, changes)?;
+            diff_subtrees(
+                db,
+                Some(old_hash),
+                Some(new_right),
+                Some(old_key),
+                new_key,
+                changes,
+            )
+        }
+    }
+}
+
+fn known_key(db: &mut dyn Database, key: Option<TrieKey>, node: &Node) -> Result<TrieKey> {
+    match key {
+        Some(key) => Ok(key),
+        None => leftmost_key(db, node.clone()),
+    }
+}
+
+// all keys below a node share their digits before its split index, if the two
+// subtrees differ before the lower split index they have no key in common and
+// every leaf was removed or added
+fn disjoint(
+    db: &mut dyn Database,
+    old_key: &TrieKey,
+    old_hash: NodeHash,
+    new_key: &TrieKey,
+    new_hash: NodeHash,
+    split: usize,
+    changes: &mut Vec<Change>,
+) -> Result<bool> {
+    let neq_idx: usize = old_key.first_diff(new_key).unwrap_or(TrieKey::BITS);
+    if neq_idx >= split {
+        return Ok(false);
+    }
+    if old_key < new_key {
+        collect(db, old_hash, Change::Removed, changes)?;
+        collect(db, new_hash, Change::Added, changes)?;
+    } else {
+        collect(db, new_hash, Change::Added, changes)?;
+        collect(db, old_hash, Change::Removed, changes)?;
+    }
+    Ok(true)
+}
+
+// every leaf below a node that exists on one side only
+fn collect(
+    db: &mut dyn Database,
+    node_hash: NodeHash,
+    change: fn(Leaf) -> Change,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let node: Node = db.get_node(&node_hash)?;
+    for leaf in leaves(db, node) {
+        changes.push(change(leaf?));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Change};
+    use crate::error::Result;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, path_len, CountingDB};
+    use crate::store::types::{Leaf, Node, Root, TrieKey};
+    use crate::trie::Trie;
+    use std::collections::BTreeMap;
+
+    fn leaf_map(trie: &mut Trie<CountingDB>) -> BTreeMap<TrieKey, Leaf> {
+        trie.leaves()
+            .map(|leaf| leaf.map(|leaf| (leaf.key, leaf)))
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut trie = Trie::new(CountingDB::default()).unwrap();
+        let keys: Vec<TrieKey> = (0..256).map(|_| generate_random_key()).collect();
+        trie.insert_batch(
+            keys.iter()
+                .map(|key| (*key, generate_random_data()))
+                .collect(),
+        )
+        .unwrap();
+        let old_root: Root = trie.root().clone();
+        let old_leafs = leaf_map(&mut trie);
+        for key in &keys[..8] {
+            trie.remove(*key).unwrap();
+        }
+        for key in &keys[8..16] {
+            trie.update(*key, generate_random_data()).unwrap();
+        }
+        for _ in 0..8 {
+            trie.insert(generate_random_key(), generate_random_data())
+                .unwrap();
+        }
+        let new_root: Root = trie.root().clone();
+        let new_leafs = leaf_map(&mut trie);
+
+        let mut expected: Vec<Change> = Vec::new();
+        for (key, old) in &old_leafs {
+            match new_leafs.get(key) {
+                None => expected.push(Change::Removed(old.clone())),
+                Some(new) if new != old => expected.push(Change::Changed {
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (key, new) in &new_leafs {
+            if !old_leafs.contains_key(key) {
+                expected.push(Change::Added(new.clone()));
+            }
+        }
+        expected.sort_by_key(|change| *change.key());
+        let changes = diff(
+            &mut trie.db,
+            Node::Root(old_root.clone()),
+            Node::Root(new_root.clone()),
+        )
+        .unwrap();
+        assert_eq!(changes, expected);
+        assert_eq!(changes.len(), 24);
+
+        // identical roots load no nodes
+        trie.db.reads = 0;
+        assert!(diff(
+            &mut trie.db,
+            Node::Root(new_root.clone()),
+            Node::Root(new_root.clone())
+        )
+        .unwrap()
+        .is_empty());
+        assert_eq!(trie.db.reads, 0);
+
+        // a single update only loads the nodes near its path
+        let old_root: Root = new_root;
+        trie.update(keys[100], generate_random_data()).unwrap();
+        let new_root: Root = trie.root().clone();
+        trie.db.reads = 0;
+        let changes = diff(
+            &mut trie.db,
+            Node::Root(old_root),
+            Node::Root(new_root.clone()),
+        )
+        .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(&changes[0], Change::Changed { new, .. } if new.key == keys[100]));
+        // one old and one new node per level
+        let reads: usize = trie.db.reads;
+        assert!(reads <= 2 * path_len(&mut trie.db, &new_root, &keys[100]));
+
+        // an insert walks to a leaf once where the new branch starts
+        let old_root: Root = new_root;
+        let key: TrieKey = generate_random_key();
+        trie.insert(key, generate_random_data()).unwrap();
+        let new_root: Root = trie.root().clone();
+        trie.db.reads = 0;
+        let changes = diff(
+            &mut trie.db,
+            Node::Root(old_root),
+            Node::Root(new_root.clone()),
+        )
+        .unwrap();
+        assert!(matches!(&changes[..], [Change::Added(leaf)] if leaf.key == key));
+        let reads: usize = trie.db.reads;
+        assert!(reads <= 4 * path_len(&mut trie.db, &new_root, &key) + 1);
+    }
+}
@@ -1,20 +1,108 @@
-use std::io::{Error, ErrorKind};
+use crate::store::types::{NodeHash, TrieKey};
+use std::fmt;
+use std::io::Error;
+
+#[derive(Debug)]
 pub enum TrieError {
+    // a leaf already exists at the key
     DuplicateLeaf,
+    // no leaf exists at the key
+    MissingLeaf,
+    // a node was found where it can't be a child, e.g. a Root below a Branch
     InvalidChild,
+    // a node was found where it can't be a parent, e.g. a Leaf above a node
     InvalidParent,
+    // a branch without 2 children or without a split index
     InvalidBranch,
-    MissingNode,
+    // a node referenced by its parent is not in the database
+    MissingNode(NodeHash),
+    // a node has a different variant than expected
+    UnexpectedNode(&'static str),
+    // a node must be hashed before it is stored
+    UnhashedNode,
+    // a stored node can't be decoded
+    CorruptedNode(String),
+    InvalidKey(&'static str),
+    InvalidProof(&'static str),
+    // a scan cursor can't be decoded
+    InvalidCursor,
+    // a root must be registered at a version after the latest one
+    InvalidVersion(u64),
+    // no root was registered at the version
+    MissingVersion(u64),
+    // no ref with the name was set
+    MissingRef(String),
+    // a prune step must visit at least one node
+    InvalidBudget,
+    // a snapshot file is malformed or doesn't match its header
+    InvalidSnapshot(&'static str),
+    // the original key of a path was not recorded
+    MissingPreimage(TrieKey),
+    // the database backend failed to read or write
+    Storage(String),
+}
+
+pub type Result<T> = std::result::Result<T, TrieError>;
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieError::DuplicateLeaf => write!(f, "Leaf already exists"),
+            TrieError::MissingLeaf => write!(f, "Leaf does not exist"),
+            TrieError::InvalidChild => write!(f, "Invalid child node"),
+            TrieError::InvalidParent => write!(f, "Invalid parent node"),
+            TrieError::InvalidBranch => write!(f, "A branch must have 2 children"),
+            TrieError::MissingNode(hash) => write!(f, "Missing node {:?}", hash),
+            TrieError::UnexpectedNode(expected) => write!(f, "Expected a {} node", expected),
+            TrieError::UnhashedNode => write!(
+                f,
+                "Must compute hash before storing a node, try calling .hash()"
+            ),
+            TrieError::CorruptedNode(reason) => write!(f, "Corrupted node: {}", reason),
+            TrieError::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            TrieError::InvalidProof(reason) => write!(f, "Invalid proof: {}", reason),
+            TrieError::InvalidCursor => write!(f, "Invalid scan cursor"),
+            TrieError::InvalidVersion(version) => {
+                write!(
+                    f,
+                    "Version {} is not after the latest version or above i64::MAX",
+                    version
+                )
+            }
+            TrieError::MissingVersion(version) => write!(f, "No root at version {}", version),
+            TrieError::MissingRef(name) => write!(f, "No ref named {}", name),
+            TrieError::InvalidBudget => write!(f, "The budget of a step must be at least 1"),
+            TrieError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {}", reason),
+            TrieError::MissingPreimage(key) => {
+                write!(f, "Missing preimage of key {}", key.to_hex())
+            }
+            TrieError::Storage(reason) => write!(f, "Storage failure: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TrieError {}
+
+impl From<rusqlite::Error> for TrieError {
+    fn from(e: rusqlite::Error) -> Self {
+        TrieError::Storage(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for TrieError {
+    fn from(e: bincode::Error) -> Self {
+        TrieError::CorruptedNode(e.to_string())
+    }
+}
+
+impl From<Error> for TrieError {
+    fn from(e: Error) -> Self {
+        TrieError::Storage(e.to_string())
+    }
 }
 
 impl From<TrieError> for Error {
     fn from(e: TrieError) -> Self {
-        match e {
-            TrieError::DuplicateLeaf => Error::new(ErrorKind::Other, "DuplicateLeaf"),
-            TrieError::InvalidChild => Error::new(ErrorKind::Other, "InvalidChild"),
-            TrieError::InvalidParent => Error::new(ErrorKind::Other, "InvalidParent"),
-            TrieError::InvalidBranch => Error::new(ErrorKind::Other, "InvalidBranch"),
-            TrieError::MissingNode => Error::new(ErrorKind::Other, "MissingNode"),
-        }
+        Error::other(e.to_string())
     }
 }
@@ -0,0 +1,187 @@
+// Iterate the leafs below a root in key order. Nodes are loaded one at a time,
+// only the hashes of the right siblings on the current path are kept.
+use crate::error::{Result, TrieError};
+use crate::store::{
+    db::Database,
+    types::{Leaf, Node, NodeHash, TrieKey},
+};
+
+pub struct Leaves<'a> {
+    db: &'a mut dyn Database,
+    // the next node is on top, a Node that was passed directly is visited first
+    start: Option<Node>,
+    stack: Vec<NodeHash>,
+}
+
+// all leafs below root_node ordered by key, root_node may also be a Branch or
+// a Leaf to iterate a subtree
+pub fn leaves(db: &mut dyn Database, root_node: Node) -> Leaves<'_> {
+    Leaves {
+        db,
+        start: Some(root_node),
+        stack: Vec::new(),
+    }
+}
+
+// the leafs below root_node ordered by key, starting at the first key that is
+// not less than start. Subtrees that end before start are never loaded.
+pub fn leaves_from<'a>(
+    db: &'a mut dyn Database,
+    root_node: Node,
+    start: &TrieKey,
+) -> Result<Leaves<'a>> {
+    // the split index of every node on the walk towards start, the right
+    // sibling if the walk went left and the hash of the next node
+    let mut path: Vec<(usize, Option<NodeHash>, NodeHash)> = Vec::new();
+    let mut current_node: Node = root_node.clone();
+    let leaf_key: TrieKey = loop {
+        let (split_idx, left, right) = match &current_node {
+            Node::Leaf(leaf) => break leaf.key,
+            Node::Branch(branch) => (
+                branch.key[0] as usize,
+                branch.left.clone(),
+                branch.right.clone(),
+            ),
+            Node::Root(root) => (0, root.left.clone(), root.right.clone()),
+        };
+        let (child_hash, sibling_hash) = if start.bit(split_idx) == 0 {
+            (left, right)
+        } else {
+            (right, None)
+        };
+        let Some(child_hash) = child_hash else {
+            if let Node::Branch(_) = current_node {
+                return Err(TrieError::InvalidBranch);
+            }
+            // an empty side of the root, only its right sibling can follow
+            let mut stack: Vec<NodeHash> = path.into_iter().filter_map(|step| step.1).collect();
+            stack.extend(sibling_hash);
+            return Ok(Leaves {
+                db,
+                start: None,
+                stack,
+            });
+        };
+        path.push((split_idx, sibling_hash, child_hash.clone()));
+        current_node = db.get_node(&child_hash)?;
+    };
+    // the keys below the first node that splits after the first digit in which
+    // the closest leaf differs from start are either all less or all greater
+    let neq_idx: Option<usize> = leaf_key.first_diff(start);
+    let cut: usize = match neq_idx {
+        Some(neq_idx) => path
+            .iter()
+            .position(|(split_idx, _, _)| *split_idx > neq_idx)
+            .unwrap_or(path.len()),
+        None => path.len(),
+    };
+    let include_subtree: bool = match neq_idx {
+        Some(neq_idx) => start.bit(neq_idx) == 0,
+        None => true,
+    };
+    let subtree_hash: Option<NodeHash> = cut.checked_sub(1).map(|idx| path[idx].2.clone());
+    let mut stack: Vec<NodeHash> = path
+        .into_iter()
+        .take(cut)
+        .filter_map(|step| step.1)
+        .collect();
+    let mut first_node: Option<Node> = None;
+    if include_subtree {
+        match subtree_hash {
+            Some(subtree_hash) => stack.push(subtree_hash),
+            None => first_node = Some(root_node),
+        }
+    }
+    Ok(Leaves {
+        db,
+        start: first_node,
+        stack,
+    })
+}
+
+impl Leaves<'_> {
+    // push the children of a node so that the left child is visited first
+    fn push_children(&mut self, node: Node) -> Result<Option<Leaf>> {
+        match node {
+            Node::Leaf(leaf) => return Ok(Some(leaf)),
+            Node::Branch(branch) => {
+                let (Some(left), Some(right)) = (branch.left, branch.right) else {
+                    return Err(TrieError::InvalidBranch);
+                };
+                self.stack.push(right);
+                self.stack.push(left);
+            }
+            Node::Root(root) => {
+                self.stack.extend(root.right);
+                self.stack.extend(root.left);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Leaves<'_> {
+    type Item = Result<Leaf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node: Result<Node> = match self.start.take() {
+                Some(node) => Ok(node),
+                None => match self.db.get_node(&self.stack.pop()?) {
+                    Ok(Node::Root(_)) => Err(TrieError::InvalidChild),
+                    result => result,
+                },
+            };
+            match node.and_then(|node| self.push_children(node)) {
+                Ok(Some(leaf)) => return Some(Ok(leaf)),
+                Ok(None) => {}
+                Err(e) => {
+                    // the iteration ends after an error
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::leaves;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key};
+    use crate::store::db::memory::InMemoryDB;
+    use crate::store::types::{Leaf, Node, Root, TrieKey};
+    use crate::trie::Trie;
+
+    #[test]
+    fn test_leaves() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let empty_root: Root = trie.root().clone();
+        assert_eq!(leaves(&mut trie.db, Node::Root(empty_root)).count(), 0);
+
+        let mut keys: Vec<TrieKey> = (0..64).map(|_| generate_random_key()).collect();
+        for key in &keys {
+            trie.insert(*key, generate_random_data()).unwrap();
+        }
+        keys.sort();
+        let root: Root = trie.root().clone();
+        let found: Vec<Leaf> = leaves(&mut trie.db, Node::Root(root.clone()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            found.iter().map(|leaf| leaf.key).collect::<Vec<TrieKey>>(),
+            keys
+        );
+        assert_eq!(trie.get(&keys[7]).unwrap(), found[7].data);
+
+        // a missing node ends the iteration with an error
+        trie.db.nodes.remove(&found[40].hash.clone().unwrap());
+        let results: Vec<_> = leaves(&mut trie.db, Node::Root(root)).collect();
+        assert_eq!(results.len(), 41);
+        assert!(matches!(
+            results.last(),
+            Some(Err(TrieError::MissingNode(_)))
+        ));
+    }
+}
@@ -1,74 +1,362 @@
 use store::{
     db::Database,
-    types::{Branch, Key, Leaf, Node, Root},
+    types::{Branch, Data, Hashable, Leaf, Node, NodeHash, Root, TrieKey},
 };
 
+pub mod diff;
 pub mod error;
+pub mod iter;
 pub mod merkle;
+pub mod scan;
+pub mod secure;
 pub mod store;
-use anyhow::{bail, Result};
+pub mod trie;
+use error::{Result, TrieError};
+use std::collections::BTreeMap;
 
-pub fn check_leaf(db: &mut dyn Database, leaf_expected: &Leaf, mut current_node: Node) -> bool {
-    #[allow(unused_assignments)]
-    let mut result: bool = false;
+pub fn check_leaf(
+    db: &mut dyn Database,
+    leaf_expected: &Leaf,
+    mut current_node: Node,
+) -> Result<bool> {
     loop {
-        match &current_node {
+        let child_hash: Option<NodeHash> = match &current_node {
             Node::Branch(branch) => {
                 let branch_prefix = &branch.key;
                 let neq_idx = &branch_prefix[0];
-                let child_idx = leaf_expected.key[*neq_idx as usize];
-                if child_idx == 0 {
-                    current_node = db.get(&branch.left.clone().unwrap()).unwrap().clone();
+                let child_idx = leaf_expected.key.bit(*neq_idx as usize);
+                let child_hash = if child_idx == 0 {
+                    branch.left.clone()
                 } else {
-                    current_node = db.get(&branch.right.clone().unwrap()).unwrap().clone();
+                    branch.right.clone()
+                };
+                if child_hash.is_none() {
+                    return Err(TrieError::InvalidBranch);
                 }
+                child_hash
             }
-            Node::Leaf(leaf) => {
-                if leaf.hash == leaf_expected.hash {
-                    result = true;
+            Node::Leaf(leaf) => return Ok(leaf.hash == leaf_expected.hash),
+            Node::Root(root) => {
+                if leaf_expected.key.bit(0) == 0 {
+                    root.left.clone()
                 } else {
-                    result = false;
+                    root.right.clone()
                 }
-                break;
             }
+        };
+        match child_hash {
+            Some(child_hash) => current_node = db.get_node(&child_hash)?,
+            None => return Ok(false),
+        }
+    }
+}
+
+// run f inside a database transaction so that either all or none of the
+// nodes it writes are stored
+fn transaction<T>(
+    db: &mut dyn Database,
+    f: impl FnOnce(&mut dyn Database) -> Result<T>,
+) -> Result<T> {
+    db.begin()?;
+    match f(db) {
+        Ok(result) => {
+            db.commit()?;
+            Ok(result)
+        }
+        Err(e) => {
+            db.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+// look up the leaf stored at the given key
+pub fn get_leaf(db: &mut dyn Database, key: &TrieKey, root_node: Node) -> Result<Option<Leaf>> {
+    let mut current_node: Node = root_node;
+    loop {
+        let child_hash: Option<NodeHash> = match current_node {
             Node::Root(root) => {
-                if leaf_expected.key[0] == 0 {
-                    match &root.left {
-                        Some(node) => {
-                            current_node = db.get(&node).unwrap().clone();
-                        }
-                        None => {
-                            result = false;
-                            break;
-                        }
-                    }
+                if key.bit(0) == 0 {
+                    root.left
                 } else {
-                    match &root.right {
-                        Some(node) => {
-                            current_node = db.get(&node).unwrap().clone();
-                        }
-                        None => {
-                            result = false;
-                            break;
-                        }
+                    root.right
+                }
+            }
+            Node::Branch(branch) => {
+                if key.bit(branch.key[0] as usize) == 0 {
+                    branch.left
+                } else {
+                    branch.right
+                }
+            }
+            Node::Leaf(leaf) => {
+                if &leaf.key == key {
+                    return Ok(Some(leaf));
+                }
+                return Ok(None);
+            }
+        };
+        let Some(child_hash) = child_hash else {
+            return Ok(None);
+        };
+        current_node = db.get_node(&child_hash)?;
+    }
+}
+
+// look up the data stored at the given key
+pub fn get_data(db: &mut dyn Database, key: &TrieKey, root_node: Node) -> Result<Option<Data>> {
+    Ok(get_leaf(db, key, root_node)?.and_then(|leaf| leaf.data))
+}
+
+pub fn insert_leaf(db: &mut dyn Database, new_leaf: &mut Leaf, root_node: Node) -> Result<Root> {
+    transaction(db, |db| {
+        // don't insert if a leaf already exists at the given key
+        if check_leaf(db, new_leaf, root_node.clone())? {
+            return Err(TrieError::DuplicateLeaf);
+        }
+        let modified_nodes = traverse_trie(db, new_leaf, root_node.clone(), false)?;
+        let mut new_root = update_modified_leafs(db, modified_nodes, root_node.unwrap_as_root()?)?;
+        new_root.hash_and_store(db)?;
+        Ok(new_root)
+    })
+}
+
+// replace the data of an existing leaf, the old root remains valid
+pub fn update_leaf(
+    db: &mut dyn Database,
+    key: TrieKey,
+    new_data: Option<Data>,
+    root_node: Node,
+) -> Result<Root> {
+    transaction(db, |db| {
+        let mut new_leaf: Leaf = Leaf::new(key, new_data);
+        new_leaf.hash();
+        let modified_nodes = traverse_trie(db, &mut new_leaf, root_node.clone(), true)?;
+        let mut new_root = update_modified_leafs(db, modified_nodes, root_node.unwrap_as_root()?)?;
+        new_root.hash_and_store(db)?;
+        Ok(new_root)
+    })
+}
+
+// remove the leaf at the given key, its parent branch is replaced by the
+// remaining sibling so that the new root equals that of a trie that never
+// contained the key
+pub fn remove_leaf(db: &mut dyn Database, key: TrieKey, root_node: Node) -> Result<Root> {
+    transaction(db, |db| {
+        let old_root: Root = root_node.unwrap_as_root()?;
+        let mut path: Vec<(u8, Branch)> = Vec::new();
+        let mut current_node_pos: u8 = key.bit(0);
+        let mut current_node: Node = match (key.bit(0), &old_root.left, &old_root.right) {
+            (0, Some(node_hash), _) | (1, _, Some(node_hash)) => db.get_node(node_hash)?,
+            _ => return Err(TrieError::MissingLeaf),
+        };
+        loop {
+            match current_node {
+                Node::Branch(branch) => {
+                    let child_pos: u8 = key.bit(branch.key[0] as usize);
+                    let child_hash = if child_pos == 0 {
+                        branch.left.clone()
+                    } else {
+                        branch.right.clone()
+                    };
+                    let Some(child_hash) = child_hash else {
+                        return Err(TrieError::InvalidBranch);
+                    };
+                    path.push((current_node_pos, branch));
+                    current_node = db.get_node(&child_hash)?;
+                    current_node_pos = child_pos;
+                }
+                Node::Leaf(leaf) => {
+                    if leaf.key != key {
+                        return Err(TrieError::MissingLeaf);
                     }
+                    break;
                 }
+                Node::Root(_) => return Err(TrieError::InvalidChild),
             }
         }
+        let Some((parent_pos, parent)) = path.pop() else {
+            // the leaf was a direct child of the root
+            let mut new_root: Root = old_root;
+            if key.bit(0) == 0 {
+                new_root.left = None;
+            } else {
+                new_root.right = None;
+            }
+            new_root.hash_and_store(db)?;
+            return Ok(new_root);
+        };
+        let sibling_hash = if current_node_pos == 0 {
+            parent.right
+        } else {
+            parent.left
+        };
+        let Some(sibling_hash) = sibling_hash else {
+            return Err(TrieError::InvalidBranch);
+        };
+        let sibling: Node = db.get_node(&sibling_hash)?;
+        let mut modified_nodes: Vec<(u8, Node)> = path
+            .into_iter()
+            .map(|(pos, branch)| (pos, Node::Branch(branch)))
+            .collect();
+        modified_nodes.push((parent_pos, sibling));
+        let mut new_root = update_modified_leafs(db, modified_nodes, old_root)?;
+        new_root.hash_and_store(db)?;
+        Ok(new_root)
+    })
+}
+
+// apply many inserts and updates at once, every new node is hashed and stored
+// exactly once and only the final root is written. If a key appears more than
+// once in the batch the last leaf wins.
+pub fn insert_batch(db: &mut dyn Database, leafs: Vec<Leaf>, root_node: Node) -> Result<Root> {
+    transaction(db, |db| {
+        let mut new_root: Root = root_node.unwrap_as_root()?;
+        let mut sorted_leafs: BTreeMap<TrieKey, Leaf> = BTreeMap::new();
+        for mut leaf in leafs {
+            leaf.hash();
+            sorted_leafs.insert(leaf.key, leaf);
+        }
+        let (left, right): (Vec<Leaf>, Vec<Leaf>) = sorted_leafs
+            .into_values()
+            .partition(|leaf| leaf.key.bit(0) == 0);
+        new_root.left = merge_subtree(db, new_root.left.clone(), left, None)?;
+        new_root.right = merge_subtree(db, new_root.right.clone(), right, None)?;
+        new_root.hash_and_store(db)?;
+        Ok(new_root)
+    })
+}
+
+// merge sorted leafs into the subtree below node_hash and return the hash of
+// the new subtree. subtree_key is a key below node_hash if one is known.
+fn merge_subtree(
+    db: &mut dyn Database,
+    node_hash: Option<NodeHash>,
+    mut leafs: Vec<Leaf>,
+    subtree_key: Option<TrieKey>,
+) -> Result<Option<NodeHash>> {
+    if leafs.is_empty() {
+        return Ok(node_hash);
+    }
+    let Some(node_hash) = node_hash else {
+        return Ok(Some(build_subtree(db, leafs)?));
+    };
+    let node: Node = db.get_node(&node_hash)?;
+    match node {
+        Node::Leaf(leaf) => {
+            // the existing leaf is kept unless the batch replaces it
+            if let Err(idx) = leafs.binary_search_by(|other| other.key.cmp(&leaf.key)) {
+                leafs.insert(idx, leaf);
+            }
+            Ok(Some(build_subtree(db, leafs)?))
+        }
+        Node::Branch(branch) => {
+            let split_idx: usize = branch.key[0] as usize;
+            // all keys below the branch share their digits before the split
+            // index. The walk follows the first leaf, so the key stays known
+            // below on the path that this leaf takes.
+            let subtree_key: TrieKey = match subtree_key {
+                Some(subtree_key) => subtree_key,
+                None => walk_key(db, Node::Branch(branch.clone()), &leafs[0].key)?,
+            };
+            let neq_idx: Option<usize> = leafs
+                .iter()
+                .filter_map(|leaf| leaf.key.first_diff(&subtree_key))
+                .filter(|idx| *idx < split_idx)
+                .min();
+            let mut new_branch: Branch = match neq_idx {
+                None => {
+                    let (left, right): (Vec<Leaf>, Vec<Leaf>) = leafs
+                        .into_iter()
+                        .partition(|leaf| leaf.key.bit(split_idx) == 0);
+                    let (left_key, right_key) = if subtree_key.bit(split_idx) == 0 {
+                        (Some(subtree_key), None)
+                    } else {
+                        (None, Some(subtree_key))
+                    };
+                    let left = merge_subtree(db, branch.left.clone(), left, left_key)?;
+                    let right = merge_subtree(db, branch.right.clone(), right, right_key)?;
+                    Branch::new(branch.key.clone(), left, right)
+                }
+                Some(neq_idx) => {
+                    // some leafs leave the subtree before its split index, a new
+                    // branch at neq_idx takes the place of the subtree
+                    let (left, right): (Vec<Leaf>, Vec<Leaf>) = leafs
+                        .into_iter()
+                        .partition(|leaf| leaf.key.bit(neq_idx) == 0);
+                    if subtree_key.bit(neq_idx) == 0 {
+                        let left = merge_subtree(db, Some(node_hash), left, Some(subtree_key))?;
+                        let right = build_subtree(db, right)?;
+                        Branch::new(vec![neq_idx as u8], left, Some(right))
+                    } else {
+                        let left = build_subtree(db, left)?;
+                        let right = merge_subtree(db, Some(node_hash), right, Some(subtree_key))?;
+                        Branch::new(vec![neq_idx as u8], Some(left), right)
+                    }
+                }
+            };
+            new_branch.hash_and_store(db)?;
+            Ok(new_branch.hash)
+        }
+        Node::Root(_) => Err(TrieError::InvalidChild),
     }
-    result
 }
 
-pub fn insert_leaf(db: &mut dyn Database, new_leaf: &mut Leaf, root_node: Node) -> Result<Root> {
-    assert_eq!(new_leaf.key.len(), 256);
-    // don't insert if a leaf already exists at the given key
-    if check_leaf(db, &new_leaf, root_node.clone()) {
-        bail!("Leaf already exists!");
+// build the subtree for a non-empty set of sorted leafs
+fn build_subtree(db: &mut dyn Database, leafs: Vec<Leaf>) -> Result<NodeHash> {
+    let (Some(first), Some(last)) = (leafs.first(), leafs.last()) else {
+        return Err(TrieError::MissingLeaf);
+    };
+    // the first and last key differ at the lowest split index of the set
+    let Some(neq_idx) = first.key.first_diff(&last.key) else {
+        let mut leaf: Leaf = first.clone();
+        leaf.hash_and_store(db)?;
+        return Ok(leaf.hash.unwrap());
+    };
+    let (left, right): (Vec<Leaf>, Vec<Leaf>) = leafs
+        .into_iter()
+        .partition(|leaf| leaf.key.bit(neq_idx) == 0);
+    let left = build_subtree(db, left)?;
+    let right = build_subtree(db, right)?;
+    let mut new_branch: Branch = Branch::new(vec![neq_idx as u8], Some(left), Some(right));
+    new_branch.hash_and_store(db)?;
+    Ok(new_branch.hash.unwrap())
+}
+
+// the key of the leftmost leaf below a node
+pub(crate) fn leftmost_key(db: &mut dyn Database, mut current_node: Node) -> Result<TrieKey> {
+    loop {
+        let child_hash = match current_node {
+            Node::Leaf(leaf) => return Ok(leaf.key),
+            Node::Branch(branch) => branch.left,
+            Node::Root(root) => root.left.or(root.right),
+        };
+        let Some(child_hash) = child_hash else {
+            return Err(TrieError::MissingLeaf);
+        };
+        current_node = db.get_node(&child_hash)?;
+    }
+}
+
+// the key of the leaf that is reached by following the bits of key, the walk
+// takes the other child where the path ends
+fn walk_key(db: &mut dyn Database, mut current_node: Node, key: &TrieKey) -> Result<TrieKey> {
+    loop {
+        let (left, right, split_idx) = match current_node {
+            Node::Leaf(leaf) => return Ok(leaf.key),
+            Node::Branch(branch) => (branch.left, branch.right, branch.key[0] as usize),
+            Node::Root(root) => (root.left, root.right, 0),
+        };
+        let child_hash = if key.bit(split_idx) == 0 {
+            left.or(right)
+        } else {
+            right.or(left)
+        };
+        let Some(child_hash) = child_hash else {
+            return Err(TrieError::MissingLeaf);
+        };
+        current_node = db.get_node(&child_hash)?;
     }
-    let modified_nodes = traverse_trie(db, new_leaf, root_node.clone(), false)?;
-    let mut new_root = update_modified_leafs(db, modified_nodes, root_node.unwrap_as_root()?)?;
-    new_root.hash_and_store(db);
-    Ok(new_root)
 }
 
 fn traverse_trie(
@@ -83,16 +371,19 @@ fn traverse_trie(
     loop {
         match &mut current_node {
             Node::Root(root) => {
-                if new_leaf.key[0] == 0 {
+                if new_leaf.key.bit(0) == 0 {
                     match root.left.clone() {
                         Some(node_hash) => {
-                            current_node = db.get(&node_hash).unwrap().clone();
+                            current_node = db.get_node(&node_hash)?;
                             current_node_pos = 0;
                         }
                         None => {
+                            if update {
+                                return Err(TrieError::MissingLeaf);
+                            }
                             let mut root: Root = current_node.unwrap_as_root()?;
                             root.left = Some(new_leaf.hash.clone().unwrap());
-                            new_leaf.store(db);
+                            new_leaf.store(db)?;
                             modified_nodes.push((0, Node::Leaf(new_leaf.clone())));
                             break;
                         }
@@ -100,13 +391,16 @@ fn traverse_trie(
                 } else {
                     match root.right.clone() {
                         Some(node_hash) => {
-                            current_node = db.get(&node_hash).unwrap().clone();
+                            current_node = db.get_node(&node_hash)?;
                             current_node_pos = 1;
                         }
                         None => {
+                            if update {
+                                return Err(TrieError::MissingLeaf);
+                            }
                             let mut root = current_node.clone().unwrap_as_root()?;
                             root.right = Some(new_leaf.hash.clone().unwrap());
-                            new_leaf.store(db);
+                            new_leaf.store(db)?;
                             modified_nodes.push((1, Node::Leaf(new_leaf.clone())));
                             break;
                         }
@@ -114,53 +408,74 @@ fn traverse_trie(
                 }
             }
             Node::Branch(branch) => {
-                if new_leaf.key[branch.key[0] as usize] == 0 {
+                if new_leaf.key.bit(branch.key[0] as usize) == 0 {
                     match branch.left.clone() {
                         Some(node_hash) => {
                             modified_nodes.push((current_node_pos, Node::Branch(branch.clone())));
-                            current_node = db.get(&node_hash).unwrap().clone();
+                            current_node = db.get_node(&node_hash)?;
                             current_node_pos = 0;
                         }
-                        None => {
-                            bail!("A branch must have 2 children")
-                        }
+                        None => return Err(TrieError::InvalidBranch),
                     }
                 } else {
                     match branch.right.clone() {
                         Some(node_hash) => {
                             modified_nodes.push((current_node_pos, Node::Branch(branch.clone())));
-                            current_node = db.get(&node_hash).unwrap().clone();
+                            current_node = db.get_node(&node_hash)?;
                             current_node_pos = 1;
                         }
-                        None => {
-                            bail!("A branch must have 2 children")
-                        }
+                        None => return Err(TrieError::InvalidBranch),
                     }
                 }
             }
             Node::Leaf(leaf) => {
                 if !update {
-                    let neq_idx = find_key_idx_not_eq(&new_leaf.key, &leaf.key)
-                        .expect("Can't insert duplicate Leaf");
-                    let new_leaf_pos: u8 = new_leaf.key[neq_idx];
+                    let neq_idx = match new_leaf.key.first_diff(&leaf.key) {
+                        Some(idx) => idx,
+                        None => return Err(TrieError::DuplicateLeaf),
+                    };
+                    let new_leaf_pos: u8 = new_leaf.key.bit(neq_idx);
                     match new_leaf.hash {
                         Some(_) => {}
-                        None => bail!("Leaf was not hashed!"),
+                        None => return Err(TrieError::UnhashedNode),
+                    }
+                    // the keys below a branch share all digits before its split index,
+                    // so the new branch belongs above the first branch on the path that
+                    // splits after neq_idx. This keeps the shape of the trie independent
+                    // of the order in which leafs were inserted.
+                    let mut sibling_pos: u8 = current_node_pos;
+                    let mut sibling_hash: Option<NodeHash> = leaf.hash.clone();
+                    if let Some(split_pos) = modified_nodes.iter().position(|(_, node)| {
+                        matches!(node, Node::Branch(branch) if branch.key[0] as usize > neq_idx)
+                    }) {
+                        let (pos, node) = modified_nodes[split_pos].clone();
+                        sibling_pos = pos;
+                        sibling_hash = node.unwrap_as_branch()?.hash;
+                        modified_nodes.truncate(split_pos);
                     }
-                    new_leaf.store(db);
+                    new_leaf.store(db)?;
                     let mut new_branch: Branch = Branch::empty(vec![neq_idx as u8]);
                     if new_leaf_pos == 0 {
                         new_branch.left = new_leaf.hash.clone();
-                        new_branch.right = leaf.hash.clone();
+                        new_branch.right = sibling_hash;
                     } else {
-                        new_branch.left = leaf.hash.clone();
+                        new_branch.left = sibling_hash;
                         new_branch.right = new_leaf.hash.clone();
                     }
-                    new_branch.hash_and_store(db);
-                    modified_nodes.push((current_node_pos, Node::Branch(new_branch)));
+                    new_branch.hash_and_store(db)?;
+                    modified_nodes.push((sibling_pos, Node::Branch(new_branch)));
                     break;
                 } else {
-                    todo!("Currently not supported");
+                    if leaf.key != new_leaf.key {
+                        return Err(TrieError::MissingLeaf);
+                    }
+                    match new_leaf.hash {
+                        Some(_) => {}
+                        None => return Err(TrieError::UnhashedNode),
+                    }
+                    new_leaf.store(db)?;
+                    modified_nodes.push((current_node_pos, Node::Leaf(new_leaf.clone())));
+                    break;
                 }
             }
         }
@@ -185,12 +500,12 @@ fn update_modified_leafs(
                     if child.0 == 0 {
                         root.left = Some(branch.clone().hash.unwrap());
                         assert!(root.left.is_some());
-                        root.hash_and_store(db);
+                        root.hash_and_store(db)?;
                         new_root = root;
                     } else {
                         root.right = Some(branch.clone().hash.unwrap());
                         assert!(root.right.is_some());
-                        root.hash_and_store(db);
+                        root.hash_and_store(db)?;
                         new_root = root;
                     }
                 }
@@ -198,97 +513,91 @@ fn update_modified_leafs(
                     if child.0 == 0 {
                         root.left = Some(leaf.clone().hash.unwrap());
                         assert!(root.left.is_some());
-                        root.hash_and_store(db);
+                        root.hash_and_store(db)?;
                         new_root = root;
                     } else {
                         root.right = Some(leaf.clone().hash.unwrap());
                         assert!(root.right.is_some());
-                        root.hash_and_store(db);
+                        root.hash_and_store(db)?;
                         new_root = root;
                     }
                 }
-                _ => bail!("This should never happen, child is root"),
+                _ => return Err(TrieError::InvalidChild),
             },
             Node::Branch(mut branch) => match &child.1 {
                 Node::Branch(child_branch) => {
                     if child.0 == 0 {
                         branch.left = Some(child_branch.clone().hash.unwrap());
-                        branch.hash_and_store(db);
+                        branch.hash_and_store(db)?;
                         modified_nodes[i] = (parent.0, Node::Branch(branch.clone()));
                     } else {
                         branch.right = Some(child_branch.clone().hash.unwrap());
-                        branch.hash_and_store(db);
+                        branch.hash_and_store(db)?;
                         modified_nodes[i] = (parent.0, Node::Branch(branch.clone()));
                     }
                 }
                 Node::Leaf(leaf) => {
                     if child.0 == 0 {
                         branch.left = Some(leaf.clone().hash.unwrap());
-                        branch.hash_and_store(db);
+                        branch.hash_and_store(db)?;
                         modified_nodes[i] = (parent.0, Node::Branch(branch.clone()));
                     } else {
                         branch.right = Some(leaf.clone().hash.unwrap());
-                        branch.hash_and_store(db);
+                        branch.hash_and_store(db)?;
                         modified_nodes[i] = (parent.0, Node::Branch(branch.clone()));
                     }
                 }
-                _ => bail!("This should never happen, child is leaf"),
+                _ => return Err(TrieError::InvalidChild),
             },
-            Node::Leaf(_) => bail!("This should never happen, parent is leaf"),
+            Node::Leaf(_) => return Err(TrieError::InvalidParent),
         }
     }
     assert!(new_root.left.is_some() || new_root.right.is_some());
     Ok(new_root)
 }
 
-fn find_key_idx_not_eq(k1: &Key, k2: &Key) -> Option<usize> {
-    // todo: find the index at which the keys are not equal
-    for (idx, digit) in k1.iter().enumerate() {
-        if digit != &k2[idx] {
-            return Some(idx);
-        }
-    }
-    None
-}
-
 #[test]
 fn test_find_key_neq() {
-    let x = vec![0, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0, 1, 1];
-    let y = vec![0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 1, 1];
-    assert_eq!(find_key_idx_not_eq(&x, &y).unwrap(), 9);
+    let mut x = vec![0, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0, 1, 1];
+    let mut y = vec![0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 1, 1];
+    x.resize(256, 0);
+    y.resize(256, 0);
+    let x = TrieKey::from_bits(&x).unwrap();
+    let y = TrieKey::from_bits(&y).unwrap();
+    assert_eq!(x.first_diff(&y).unwrap(), 9);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::merkle::tests::{generate_random_data, generate_random_key};
-    use crate::store::db::sql::TrieDB;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{
+        generate_random_data, generate_random_key, path_len, CountingDB, TempPath,
+    };
+    use crate::store::db::{memory::InMemoryDB, Database};
     use crate::store::types::Leaf;
-    use crate::store::types::{Hashable, Node, Root};
-    use crate::{check_leaf, insert_leaf};
+    use crate::store::types::{Hashable, Node, Root, TrieKey};
+    use crate::{
+        check_leaf, get_data, get_leaf, insert_batch, insert_leaf, remove_leaf, update_leaf,
+    };
     use colored::*;
     use indicatif::ProgressBar;
-    use std::env;
     use std::time::Instant;
     #[test]
     fn test_insert_leaf() {
         let start_time = Instant::now();
-        let mut db = TrieDB {
-            path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-            cache: None,
-        };
-        db.setup();
-        let mut leaf_1: Leaf = Leaf::empty(vec![0u8; 256]);
+        let mut db = InMemoryDB::new();
+        let mut leaf_1: Leaf = Leaf::empty(TrieKey::new([0u8; 32]));
         let mut leaf_2_key: Vec<u8> = vec![0; 253];
         for _i in 0..3 {
             leaf_2_key.push(1);
         }
-        let mut leaf_2: Leaf = Leaf::empty(leaf_2_key);
+        let mut leaf_2: Leaf = Leaf::empty(TrieKey::from_bits(&leaf_2_key).unwrap());
 
         let mut leaf_3_key: Vec<u8> = vec![0; 253];
         for _i in 0..3 {
             leaf_3_key.push(0);
         }
-        let mut leaf_3 = Leaf::empty(leaf_3_key);
+        let mut leaf_3 = Leaf::empty(TrieKey::from_bits(&leaf_3_key).unwrap());
         leaf_1.hash();
         leaf_2.hash();
         leaf_3.hash();
@@ -297,8 +606,8 @@ mod tests {
         let mut new_root = insert_leaf(&mut db, &mut leaf_1, root_node.clone()).unwrap();
         new_root = insert_leaf(&mut db, &mut leaf_2, Node::Root(new_root)).unwrap();
 
-        assert!(check_leaf(&mut db, &leaf_1, Node::Root(new_root.clone())));
-        assert!(check_leaf(&mut db, &leaf_2, Node::Root(new_root.clone())));
+        assert!(check_leaf(&mut db, &leaf_1, Node::Root(new_root.clone())).unwrap());
+        assert!(check_leaf(&mut db, &leaf_2, Node::Root(new_root.clone())).unwrap());
 
         println!(
             "{} Elapsed Time: {} µs",
@@ -307,6 +616,173 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_update_leaf() {
+        let mut db = InMemoryDB::new();
+        let mut leaf_1: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+        let mut leaf_2: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+        leaf_1.hash();
+        leaf_2.hash();
+        let root_node = Node::Root(Root::empty());
+        let old_root = insert_leaf(&mut db, &mut leaf_1, root_node).unwrap();
+        let old_root = insert_leaf(&mut db, &mut leaf_2, Node::Root(old_root)).unwrap();
+
+        let new_data = generate_random_data();
+        let new_root = update_leaf(
+            &mut db,
+            leaf_2.key,
+            Some(new_data.clone()),
+            Node::Root(old_root.clone()),
+        )
+        .unwrap();
+        let mut updated_leaf: Leaf = Leaf::new(leaf_2.key, Some(new_data));
+        updated_leaf.hash();
+        assert_ne!(new_root.hash, old_root.hash);
+        assert!(check_leaf(&mut db, &updated_leaf, Node::Root(new_root.clone())).unwrap());
+        assert!(check_leaf(&mut db, &leaf_1, Node::Root(new_root.clone())).unwrap());
+        assert!(!check_leaf(&mut db, &leaf_2, Node::Root(new_root.clone())).unwrap());
+        // the old root still resolves to the old data
+        assert!(check_leaf(&mut db, &leaf_2, Node::Root(old_root.clone())).unwrap());
+        assert!(!check_leaf(&mut db, &updated_leaf, Node::Root(old_root)).unwrap());
+
+        // updating a key that is not in the trie fails
+        assert!(matches!(
+            update_leaf(
+                &mut db,
+                generate_random_key(),
+                None,
+                Node::Root(new_root.clone())
+            ),
+            Err(TrieError::MissingLeaf)
+        ));
+        // inserting a key that is already in the trie fails
+        let mut duplicate_leaf: Leaf = Leaf::new(leaf_1.key, Some(generate_random_data()));
+        duplicate_leaf.hash();
+        assert!(matches!(
+            insert_leaf(&mut db, &mut duplicate_leaf, Node::Root(new_root)),
+            Err(TrieError::DuplicateLeaf)
+        ));
+    }
+
+    #[test]
+    fn test_remove_leaf() {
+        let mut db = InMemoryDB::new();
+        let mut leafs: Vec<Leaf> = Vec::new();
+        for _ in 0..32 {
+            let mut leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+            leaf.hash();
+            leafs.push(leaf);
+        }
+        // the shape of the trie does not depend on the insertion order
+        let mut root_a: Root = Root::empty();
+        for leaf in leafs.iter_mut() {
+            root_a = insert_leaf(&mut db, leaf, Node::Root(root_a)).unwrap();
+        }
+        let mut root_b: Root = Root::empty();
+        for leaf in leafs.iter_mut().skip(1).rev() {
+            root_b = insert_leaf(&mut db, leaf, Node::Root(root_b)).unwrap();
+        }
+        // removing a leaf yields the root of a trie that never contained it
+        let removed: Root = remove_leaf(&mut db, leafs[0].key, Node::Root(root_a.clone())).unwrap();
+        assert_eq!(removed.hash, root_b.hash);
+        assert!(!check_leaf(&mut db, &leafs[0], Node::Root(removed.clone())).unwrap());
+        for leaf in leafs.iter().skip(1) {
+            assert!(check_leaf(&mut db, leaf, Node::Root(removed.clone())).unwrap());
+        }
+        // the old root is left untouched
+        assert!(check_leaf(&mut db, &leafs[0], Node::Root(root_a)).unwrap());
+        // removing a key that is not in the trie fails
+        assert!(remove_leaf(&mut db, leafs[0].key, Node::Root(removed.clone())).is_err());
+
+        let mut current_root: Root = removed;
+        for leaf in leafs.iter().skip(1) {
+            current_root = remove_leaf(&mut db, leaf.key, Node::Root(current_root)).unwrap();
+        }
+        assert!(current_root.left.is_none() && current_root.right.is_none());
+    }
+
+    #[test]
+    fn test_get_leaf() {
+        let mut db = InMemoryDB::new();
+        let root_node = Node::Root(Root::empty());
+        // lookups in an empty trie end on a missing root child
+        assert!(get_leaf(&mut db, &generate_random_key(), root_node.clone())
+            .unwrap()
+            .is_none());
+        let mut leaf_1: Leaf = Leaf::new(TrieKey::new([0u8; 32]), Some(generate_random_data()));
+        let mut leaf_2: Leaf = Leaf::new(TrieKey::new([0xff; 32]), None);
+        leaf_1.hash();
+        leaf_2.hash();
+        let root = insert_leaf(&mut db, &mut leaf_1, root_node).unwrap();
+        let root = insert_leaf(&mut db, &mut leaf_2, Node::Root(root)).unwrap();
+
+        let found = get_leaf(&mut db, &leaf_1.key, Node::Root(root.clone())).unwrap();
+        assert_eq!(found, Some(leaf_1.clone()));
+        assert_eq!(
+            get_data(&mut db, &leaf_1.key, Node::Root(root.clone())).unwrap(),
+            leaf_1.data
+        );
+        assert_eq!(
+            get_data(&mut db, &leaf_2.key, Node::Root(root.clone())).unwrap(),
+            None
+        );
+        // the walk for this key ends on leaf_1
+        let mut missing_key: Vec<u8> = vec![0u8; 256];
+        missing_key[255] = 1;
+        let missing_key = TrieKey::from_bits(&missing_key).unwrap();
+        assert!(get_leaf(&mut db, &missing_key, Node::Root(root))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut db = InMemoryDB::new();
+        let mut leafs: Vec<Leaf> = Vec::new();
+        for _ in 0..64 {
+            let mut leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+            leaf.hash();
+            leafs.push(leaf);
+        }
+        let mut sequential_root: Root = Root::empty();
+        for leaf in leafs.iter_mut().take(32) {
+            sequential_root = insert_leaf(&mut db, leaf, Node::Root(sequential_root)).unwrap();
+        }
+        let batch_root: Root =
+            insert_batch(&mut db, leafs[..32].to_vec(), Node::Root(Root::empty())).unwrap();
+        assert_eq!(batch_root.hash, sequential_root.hash);
+
+        // the next block inserts new leafs and updates some existing ones
+        let mut block: Vec<Leaf> = leafs[32..].to_vec();
+        for leaf in leafs.iter().take(8) {
+            let new_data = generate_random_data();
+            sequential_root = update_leaf(
+                &mut db,
+                leaf.key,
+                Some(new_data.clone()),
+                Node::Root(sequential_root),
+            )
+            .unwrap();
+            block.push(Leaf::new(leaf.key, Some(new_data)));
+        }
+        for leaf in leafs.iter_mut().skip(32) {
+            sequential_root = insert_leaf(&mut db, leaf, Node::Root(sequential_root)).unwrap();
+        }
+        let batch_root: Root = insert_batch(&mut db, block, Node::Root(batch_root)).unwrap();
+        assert_eq!(batch_root.hash, sequential_root.hash);
+        for leaf in leafs.iter().skip(8) {
+            assert!(check_leaf(&mut db, leaf, Node::Root(batch_root.clone())).unwrap());
+        }
+
+        // a single leaf walks to a leaf once and then only loads its path
+        let mut db = CountingDB { db, reads: 0 };
+        let leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+        let new_root: Root =
+            insert_batch(&mut db, vec![leaf.clone()], Node::Root(batch_root)).unwrap();
+        let reads: usize = db.reads;
+        assert!(reads <= 2 * path_len(&mut db, &new_root, &leaf.key));
+    }
+
     #[test]
     fn test_many_leafs() {
         let transaction_count: u32 = std::env::var("INSERT_TRANSACTION_COUNT")
@@ -320,21 +796,14 @@ mod tests {
             transactions.push(leaf);
         }
         let start_time = Instant::now();
-        let mut db = TrieDB {
-            path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-            cache: None,
-        };
+        let mut db = InMemoryDB::new();
         let root: Root = Root::empty();
         let mut root_node = Node::Root(root);
         let progress_bar: ProgressBar = ProgressBar::new(transaction_count as u64);
         for mut leaf in transactions {
             leaf.hash();
             let new_root = insert_leaf(&mut db, &mut leaf, root_node.clone()).unwrap();
-            assert!(check_leaf(
-                &mut db,
-                &leaf.clone(),
-                Node::Root(new_root.clone())
-            ));
+            assert!(check_leaf(&mut db, &leaf.clone(), Node::Root(new_root.clone())).unwrap());
             root_node = Node::Root(new_root.clone());
             progress_bar.inc(1);
         }
@@ -346,28 +815,117 @@ mod tests {
         );
     }
 
+    fn check_transactions(db: &mut dyn Database) {
+        let mut leafs: Vec<Leaf> = Vec::new();
+        for _ in 0..16 {
+            let mut leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+            leaf.hash();
+            leafs.push(leaf);
+        }
+        // all inserts of a block are committed together
+        let mut block_root: Root = Root::empty();
+        db.begin().unwrap();
+        for leaf in leafs.iter_mut().take(8) {
+            block_root = insert_leaf(db, leaf, Node::Root(block_root)).unwrap();
+        }
+        db.commit().unwrap();
+        for leaf in leafs.iter().take(8) {
+            assert!(check_leaf(db, leaf, Node::Root(block_root.clone())).unwrap());
+        }
+        // a discarded block leaves no nodes behind
+        let mut discarded_root: Root = block_root.clone();
+        db.begin().unwrap();
+        for leaf in leafs.iter_mut().skip(8) {
+            discarded_root = insert_leaf(db, leaf, Node::Root(discarded_root)).unwrap();
+        }
+        db.rollback().unwrap();
+        assert!(db.get(&discarded_root.hash.unwrap()).unwrap().is_none());
+        assert!(db.get(&leafs[8].hash.clone().unwrap()).unwrap().is_none());
+        assert!(db.get(&block_root.hash.clone().unwrap()).unwrap().is_some());
+
+        // an inner rollback keeps the writes of the outer transaction and
+        // an outer rollback discards the committed inner ones
+        db.begin().unwrap();
+        let outer_root = insert_leaf(db, &mut leafs[8], Node::Root(block_root.clone())).unwrap();
+        db.begin().unwrap();
+        let inner_root = insert_leaf(db, &mut leafs[9], Node::Root(outer_root.clone())).unwrap();
+        db.rollback().unwrap();
+        assert!(db.get(&inner_root.hash.unwrap()).unwrap().is_none());
+        db.begin().unwrap();
+        let inner_root = insert_leaf(db, &mut leafs[10], Node::Root(outer_root.clone())).unwrap();
+        db.commit().unwrap();
+        assert!(db.get(&outer_root.hash.clone().unwrap()).unwrap().is_some());
+        db.rollback().unwrap();
+        assert!(db.get(&outer_root.hash.unwrap()).unwrap().is_none());
+        assert!(db.get(&inner_root.hash.unwrap()).unwrap().is_none());
+        assert!(check_leaf(db, &leafs[0], Node::Root(block_root)).unwrap());
+    }
+
+    #[test]
+    fn test_sql_transactions() {
+        use crate::store::db::sql::TrieDB;
+
+        let path = TempPath::new("sql_transactions.sqlite");
+        check_transactions(&mut TrieDB::open(path.to_str()).unwrap());
+    }
+
+    #[test]
+    fn test_memory_transactions() {
+        let mut db = InMemoryDB::new();
+        check_transactions(&mut db);
+        // node removals are undone as well
+        let keys: Vec<Vec<u8>> = db.node_keys(None, usize::MAX).unwrap();
+        db.begin().unwrap();
+        for key in &keys {
+            db.remove(key).unwrap();
+        }
+        db.rollback().unwrap();
+        assert_eq!(db.node_keys(None, usize::MAX).unwrap(), keys);
+    }
+
+    #[test]
+    fn test_storage_errors() {
+        use crate::merkle::merkle_proof;
+        use crate::store::db::sql::TrieDB;
+
+        // a database that can't be opened surfaces as an error
+        assert!(matches!(
+            TrieDB::open("missing-directory/database.sqlite"),
+            Err(TrieError::Storage(_))
+        ));
+
+        // so does a node that is missing from the database
+        let mut leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+        leaf.hash();
+        let mut db = InMemoryDB::new();
+        let root = insert_leaf(&mut db, &mut leaf, Node::Root(Root::empty())).unwrap();
+        db.nodes.clear();
+        assert!(matches!(
+            check_leaf(&mut db, &leaf, Node::Root(root.clone())),
+            Err(TrieError::MissingNode(hash)) if leaf.hash.as_ref() == Some(&hash)
+        ));
+        assert!(merkle_proof(&mut db, leaf.key, Node::Root(root)).is_err());
+    }
+
     #[test]
     fn test_sql_db() {
         use crate::merkle::{merkle_proof, verify_merkle_proof};
         use crate::store::db::sql::TrieDB;
 
         let start_time = Instant::now();
-        let mut db = TrieDB {
-            path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-            cache: None,
-        };
-        db.setup();
-        let mut leaf_1: Leaf = Leaf::empty(vec![0u8; 256]);
+        let path = TempPath::new("sql_db.sqlite");
+        let mut db = TrieDB::open(path.to_str()).unwrap();
+        let mut leaf_1: Leaf = Leaf::empty(TrieKey::new([0u8; 32]));
         let mut leaf_2_key: Vec<u8> = vec![0; 253];
         for _i in 0..3 {
             leaf_2_key.push(1);
         }
-        let mut leaf_2: Leaf = Leaf::empty(leaf_2_key);
+        let mut leaf_2: Leaf = Leaf::empty(TrieKey::from_bits(&leaf_2_key).unwrap());
         let mut leaf_3_key: Vec<u8> = vec![0; 253];
         for _i in 0..3 {
             leaf_3_key.push(0);
         }
-        let mut leaf_3 = Leaf::empty(leaf_3_key);
+        let mut leaf_3 = Leaf::empty(TrieKey::from_bits(&leaf_3_key).unwrap());
         leaf_1.hash();
         leaf_2.hash();
         leaf_3.hash();
@@ -380,13 +938,23 @@ mod tests {
             "[1x Insert]".yellow(),
             &start_time.elapsed().as_micros().to_string().blue()
         );
-        let mut leaf_1: Leaf = Leaf::empty(vec![1u8; 256]);
+        let mut leaf_1: Leaf = Leaf::empty(TrieKey::new([0xff; 32]));
         leaf_1.hash();
         let root: Root = new_root.unwrap();
         let root_node: Node = Node::Root(root);
         let new_root: Root = insert_leaf(&mut db, &mut leaf_1, root_node).unwrap();
         let proof = merkle_proof(&mut db, leaf_1.key, Node::Root(new_root.clone()));
         let inner_proof = proof.unwrap().nodes;
-        verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap();
+        assert!(verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap());
+
+        // the in-memory backend produces the same roots
+        let mut memory_db = InMemoryDB::new();
+        let memory_root: Root = insert_batch(
+            &mut memory_db,
+            vec![Leaf::empty(TrieKey::new([0u8; 32])), leaf_2, leaf_1],
+            Node::Root(Root::empty()),
+        )
+        .unwrap();
+        assert_eq!(memory_root.hash, new_root.hash);
     }
 }
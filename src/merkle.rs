@@ -1,119 +1,738 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 // Compute Merkle Proof for a Leaf at a given point in time (e.g. at a Snapshot)
+use crate::error::{Result, TrieError};
+use crate::scan::range_scan;
 use crate::store::{
     db::Database,
-    types::{Hashable, Node, NodeHash, RootHash},
+    types::{Branch, Data, Hashable, Leaf, Node, NodeHash, Root, RootHash, TrieKey},
 };
-use anyhow::{bail, Result};
 // obtain the merkle path for a leaf
-pub fn merkle_proof(db: &mut dyn Database, key: Vec<u8>, trie_root: Node) -> Result<MerkleProof> {
-    assert_eq!(key.len(), 256);
+pub fn merkle_proof(db: &mut dyn Database, key: TrieKey, trie_root: Node) -> Result<MerkleProof> {
     let mut proof: MerkleProof = MerkleProof { nodes: Vec::new() };
     let mut current_node = trie_root.clone();
     loop {
         match &mut current_node {
             Node::Root(root) => {
                 proof.nodes.push((false, Node::Root(root.clone())));
-                if key[0] == 0 {
-                    let left_child = db.get(&root.left.clone().unwrap()).unwrap();
-                    current_node = left_child.clone();
-                    proof.nodes.push((false, left_child.clone()));
+                let child_hash = if key.bit(0) == 0 {
+                    root.left.clone()
                 } else {
-                    let right_child = db.get(&root.right.clone().unwrap()).unwrap();
-                    current_node = right_child.clone();
-                    proof.nodes.push((true, right_child.clone()));
-                }
+                    root.right.clone()
+                };
+                let Some(child_hash) = child_hash else {
+                    return Err(TrieError::MissingLeaf);
+                };
+                current_node = db.get_node(&child_hash)?;
+                proof.nodes.push((key.bit(0) != 0, current_node.clone()));
             }
             Node::Branch(branch) => {
-                let digit = key[branch.key[0] as usize];
-                if digit == 0 {
-                    current_node = db.get(&branch.left.clone().unwrap()).unwrap().clone();
-                    proof.nodes.push((false, current_node.clone()));
+                let digit = key.bit(*branch.key.first().ok_or(TrieError::InvalidBranch)? as usize);
+                let child_hash = if digit == 0 {
+                    branch.left.clone()
                 } else {
-                    current_node = db.get(&branch.right.clone().unwrap()).unwrap().clone();
-                    proof.nodes.push((true, current_node.clone()));
+                    branch.right.clone()
+                };
+                let Some(child_hash) = child_hash else {
+                    return Err(TrieError::InvalidBranch);
+                };
+                current_node = db.get_node(&child_hash)?;
+                proof.nodes.push((digit != 0, current_node.clone()));
+            }
+            Node::Leaf(leaf) => {
+                if leaf.key != key {
+                    return Err(TrieError::MissingLeaf);
                 }
+                return Ok(proof);
             }
-            Node::Leaf(_) => return Ok(proof),
         }
     }
 }
 
+// returns Ok(false) if the proof does not match the state root hash and
+// an error if the proof is malformed
 pub fn verify_merkle_proof(
     mut inner_proof: Vec<(bool, Node)>,
     state_root_hash: RootHash,
-) -> Result<()> {
+) -> Result<bool> {
     inner_proof.reverse();
-    let mut current_hash: Option<(bool, NodeHash)> = None;
+    let mut nodes = inner_proof.into_iter();
+    let Some((direction, node)) = nodes.next() else {
+        return Err(TrieError::InvalidProof("Merkle Proof is empty"));
+    };
+    let Node::Leaf(mut leaf) = node else {
+        return Err(TrieError::InvalidProof("Merkle Proof must end with a Leaf"));
+    };
+    leaf.hash();
+    let mut current_hash: (bool, NodeHash) = (direction, leaf.hash.unwrap());
     let mut root_hash: Option<RootHash> = None;
-    for (idx, node) in inner_proof.into_iter().enumerate() {
-        if idx == 0 {
-            let leaf = node.1.unwrap_as_leaf()?;
-            current_hash = Some((node.0, leaf.hash.unwrap()));
-        } else {
-            match node.1 {
-                Node::Root(mut root) => {
-                    if !current_hash.clone().unwrap().0 {
-                        root.left = Some(current_hash.clone().unwrap().1);
+    for (direction, node) in nodes {
+        if root_hash.is_some() {
+            return Err(TrieError::InvalidProof(
+                "Root must be the first node of a Merkle Proof",
+            ));
+        }
+        match node {
+            Node::Root(mut root) => {
+                if !current_hash.0 {
+                    root.left = Some(current_hash.1.clone());
+                } else {
+                    root.right = Some(current_hash.1.clone());
+                }
+                root.hash();
+                root_hash = root.hash;
+            }
+            Node::Branch(mut branch) => {
+                if !current_hash.0 {
+                    branch.left = Some(current_hash.1);
+                } else {
+                    branch.right = Some(current_hash.1);
+                }
+                branch.hash();
+                current_hash = (direction, branch.hash.unwrap());
+            }
+            Node::Leaf(_) => {
+                return Err(TrieError::InvalidProof(
+                    "Invalid Node variant in Merkle Proof",
+                ))
+            }
+        }
+    }
+    match root_hash {
+        // the merkle proof is valid for the given root hash
+        Some(root_hash) => Ok(root_hash == state_root_hash),
+        None => Err(TrieError::InvalidProof("Merkle Proof is missing the Root")),
+    }
+}
+
+// verify a proof for the leaf stored at key, every step of the path must follow
+// the digit of the key at the split index of its parent. If expected_data is
+// given the leaf must also carry that data.
+pub fn verify_merkle_proof_for_key(
+    inner_proof: Vec<(bool, Node)>,
+    key: &TrieKey,
+    expected_data: Option<&Data>,
+    state_root_hash: RootHash,
+) -> Result<bool> {
+    let Some((_, Node::Leaf(leaf))) = inner_proof.last() else {
+        return Err(TrieError::InvalidProof("Merkle Proof must end with a Leaf"));
+    };
+    if &leaf.key != key {
+        return Ok(false);
+    }
+    if let Some(expected_data) = expected_data {
+        if leaf.data.as_ref() != Some(expected_data) {
+            return Ok(false);
+        }
+    }
+    for step in inner_proof.windows(2) {
+        let (parent, (direction, _)) = (&step[0].1, &step[1]);
+        let expected_direction: bool = match parent {
+            Node::Root(_) => key.bit(0) != 0,
+            Node::Branch(branch) => match branch.key.first() {
+                Some(split_idx) => key.bit(*split_idx as usize) != 0,
+                None => return Err(TrieError::InvalidBranch),
+            },
+            Node::Leaf(_) => {
+                return Err(TrieError::InvalidProof(
+                    "Invalid Node variant in Merkle Proof",
+                ))
+            }
+        };
+        if *direction != expected_direction {
+            return Ok(false);
+        }
+    }
+    verify_merkle_proof(inner_proof, state_root_hash)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub nodes: Vec<(bool, Node)>,
+}
+
+impl MerkleProof {
+    // strip the proof down to the leaf and the sibling hash at every level
+    pub fn compact(&self) -> Result<CompactMerkleProof> {
+        let Some((_, Node::Root(root))) = self.nodes.first() else {
+            return Err(TrieError::InvalidProof(
+                "Merkle Proof must start with the Root",
+            ));
+        };
+        let Some((_, Node::Leaf(leaf))) = self.nodes.last() else {
+            return Err(TrieError::InvalidProof("Merkle Proof must end with a Leaf"));
+        };
+        let mut compact_proof = CompactMerkleProof {
+            leaf: leaf.clone(),
+            root_sibling: None,
+            branches: Vec::new(),
+        };
+        for (idx, (_, node)) in self.nodes.iter().enumerate() {
+            let Some((direction, _)) = self.nodes.get(idx + 1) else {
+                break;
+            };
+            match node {
+                Node::Root(_) => {
+                    compact_proof.root_sibling = if *direction {
+                        root.left.clone()
                     } else {
-                        root.right = Some(current_hash.clone().unwrap().1);
-                    }
-                    root.hash();
-                    root_hash = root.hash;
+                        root.right.clone()
+                    };
                 }
-                Node::Branch(mut branch) => {
-                    if !current_hash.clone().unwrap().0 {
-                        branch.left = Some(current_hash.clone().unwrap().1);
+                Node::Branch(branch) => {
+                    let sibling = if *direction {
+                        branch.left.clone()
                     } else {
-                        branch.right = Some(current_hash.clone().unwrap().1);
+                        branch.right.clone()
+                    };
+                    match sibling {
+                        Some(sibling) => compact_proof.branches.push((
+                            *branch.key.first().ok_or(TrieError::InvalidBranch)?,
+                            sibling,
+                        )),
+                        None => return Err(TrieError::InvalidBranch),
                     }
-                    branch.hash();
-                    current_hash = Some((node.0, branch.hash.unwrap()));
                 }
-                Node::Leaf(_) => bail!("Invalid Node variant in Merkle Proof"),
+                Node::Leaf(_) => {
+                    return Err(TrieError::InvalidProof(
+                        "Invalid Node variant in Merkle Proof",
+                    ))
+                }
             }
         }
+        Ok(compact_proof)
     }
-    // if this assertion passes, the merkle proof is valid
-    // for the given root hash
-    assert_eq!(&state_root_hash, &root_hash.unwrap());
-    Ok(())
 }
 
+// A Merkle Proof that only carries the hashes needed to recompute the root,
+// the path is taken from the digits of the leaf key at each split index
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct MerkleProof {
+pub struct CompactMerkleProof {
+    pub leaf: Leaf,
+    // the other child of the root, if any
+    pub root_sibling: Option<NodeHash>,
+    // split index and sibling hash of every branch from the root down to the leaf
+    pub branches: Vec<(u8, NodeHash)>,
+}
+
+pub fn verify_compact_merkle_proof(
+    proof: CompactMerkleProof,
+    state_root_hash: RootHash,
+) -> Result<bool> {
+    let mut leaf: Leaf = proof.leaf;
+    leaf.hash();
+    let mut current_hash: NodeHash = leaf.hash.unwrap();
+    for (split_idx, sibling) in proof.branches.into_iter().rev() {
+        let mut branch: Branch = Branch::empty(vec![split_idx]);
+        if leaf.key.bit(split_idx as usize) == 0 {
+            branch.update(Some(current_hash), Some(sibling));
+        } else {
+            branch.update(Some(sibling), Some(current_hash));
+        }
+        branch.hash();
+        current_hash = branch.hash.unwrap();
+    }
+    let mut root: Root = Root::empty();
+    if leaf.key.bit(0) == 0 {
+        root.left = Some(current_hash);
+        root.right = proof.root_sibling;
+    } else {
+        root.left = proof.root_sibling;
+        root.right = Some(current_hash);
+    }
+    root.hash();
+    Ok(root.hash == Some(state_root_hash))
+}
+
+// obtain the path that proves that no leaf exists at a key, the walk
+// either ends at an empty child of the root or at a leaf with another key
+pub fn exclusion_proof(
+    db: &mut dyn Database,
+    key: TrieKey,
+    trie_root: Node,
+) -> Result<ExclusionProof> {
+    let mut proof: ExclusionProof = ExclusionProof { nodes: Vec::new() };
+    let mut current_node = trie_root.clone();
+    loop {
+        let (digit, child_hash) = match &current_node {
+            Node::Root(root) => {
+                proof.nodes.push((false, current_node.clone()));
+                if key.bit(0) == 0 {
+                    (key.bit(0), root.left.clone())
+                } else {
+                    (key.bit(0), root.right.clone())
+                }
+            }
+            Node::Branch(branch) => {
+                let digit = key.bit(*branch.key.first().ok_or(TrieError::InvalidBranch)? as usize);
+                let child_hash = if digit == 0 {
+                    branch.left.clone()
+                } else {
+                    branch.right.clone()
+                };
+                if child_hash.is_none() {
+                    return Err(TrieError::InvalidBranch);
+                }
+                (digit, child_hash)
+            }
+            Node::Leaf(leaf) => {
+                if leaf.key == key {
+                    return Err(TrieError::DuplicateLeaf);
+                }
+                return Ok(proof);
+            }
+        };
+        match child_hash {
+            Some(child_hash) => {
+                current_node = db.get_node(&child_hash)?;
+                proof.nodes.push((digit != 0, current_node.clone()));
+            }
+            None => return Ok(proof),
+        }
+    }
+}
+
+// verify that the walk for a key terminates without reaching a leaf
+// with that key, returns Ok(false) if the proof does not match the state
+// root hash or the key and an error if the proof is malformed
+pub fn verify_exclusion_proof(
+    inner_proof: Vec<(bool, Node)>,
+    key: TrieKey,
+    state_root_hash: RootHash,
+) -> Result<bool> {
+    let mut nodes = inner_proof.into_iter();
+    let Some((_, Node::Root(mut root))) = nodes.next() else {
+        return Err(TrieError::InvalidProof(
+            "Exclusion proof must start with the Root",
+        ));
+    };
+    root.hash();
+    if root.hash.as_ref() != Some(&state_root_hash) {
+        return Ok(false);
+    }
+    let mut expected_direction: bool = key.bit(0) != 0;
+    let mut expected_hash: Option<NodeHash> = if expected_direction {
+        root.right
+    } else {
+        root.left
+    };
+    let mut reached_leaf: bool = false;
+    for (direction, node) in nodes {
+        let Some(node_hash) = expected_hash.take() else {
+            return Err(TrieError::InvalidProof(
+                "Exclusion proof continues past the end of the walk",
+            ));
+        };
+        if direction != expected_direction {
+            return Ok(false);
+        }
+        match node {
+            Node::Branch(mut branch) => {
+                branch.hash();
+                if branch.hash != Some(node_hash) {
+                    return Ok(false);
+                }
+                let Some(split_idx) = branch.key.first() else {
+                    return Err(TrieError::InvalidBranch);
+                };
+                expected_direction = key.bit(*split_idx as usize) != 0;
+                expected_hash = if expected_direction {
+                    branch.right
+                } else {
+                    branch.left
+                };
+            }
+            Node::Leaf(mut leaf) => {
+                leaf.hash();
+                if leaf.hash != Some(node_hash) || leaf.key == key {
+                    return Ok(false);
+                }
+                reached_leaf = true;
+            }
+            Node::Root(_) => {
+                return Err(TrieError::InvalidProof(
+                    "Invalid Node variant in Exclusion proof",
+                ))
+            }
+        }
+    }
+    // the walk must end at an empty child of the root or at a leaf
+    if !reached_leaf && expected_hash.is_some() {
+        return Err(TrieError::InvalidProof("Exclusion proof is incomplete"));
+    }
+    Ok(true)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExclusionProof {
     pub nodes: Vec<(bool, Node)>,
 }
 
+// Proves the presence or absence of many keys at once, every node that is on
+// the path of more than one key is only included once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MultiProof {
+    // the Root followed by the nodes on the paths of all keys
+    pub nodes: Vec<Node>,
+    pub keys: Vec<TrieKey>,
+}
+
+impl MultiProof {
+    // hash every node once and check the paths of all keys, returns None if
+    // the proof does not match the state root hash
+    pub fn verify(&self, state_root_hash: RootHash) -> Result<Option<VerifiedMultiProof>> {
+        let (root, index) = index_nodes(&self.nodes)?;
+        if root.hash != Some(state_root_hash) {
+            return Ok(None);
+        }
+        for key in &self.keys {
+            walk_index(&root, &index, key)?;
+        }
+        Ok(Some(VerifiedMultiProof { root, index }))
+    }
+}
+
+// A MultiProof that matched a root, the nodes are indexed by their hash so
+// that every lookup only walks the path of its key
+pub struct VerifiedMultiProof {
+    root: Root,
+    index: HashMap<NodeHash, Node>,
+}
+
+impl VerifiedMultiProof {
+    // the leaf stored at key, None if the proof shows that the key is absent
+    pub fn lookup(&self, key: &TrieKey) -> Result<Option<Leaf>> {
+        let (_, leaf) = walk_index(&self.root, &self.index, key)?;
+        Ok(leaf.filter(|leaf| &leaf.key == key))
+    }
+}
+
+// the Root of a proof and the other nodes indexed by their recomputed hash
+fn index_nodes(nodes: &[Node]) -> Result<(Root, HashMap<NodeHash, Node>)> {
+    let Some(Node::Root(root)) = nodes.first() else {
+        return Err(TrieError::InvalidProof("Proof must start with the Root"));
+    };
+    let mut root: Root = root.clone();
+    root.hash();
+    let mut index: HashMap<NodeHash, Node> = HashMap::new();
+    for node in &nodes[1..] {
+        let node_hash: Option<NodeHash> = match node.clone() {
+            Node::Branch(mut branch) => {
+                branch.hash();
+                branch.hash
+            }
+            Node::Leaf(mut leaf) => {
+                leaf.hash();
+                leaf.hash
+            }
+            Node::Root(_) => return Err(TrieError::InvalidProof("Invalid Node variant in Proof")),
+        };
+        index.insert(node_hash.unwrap(), node.clone());
+    }
+    Ok((root, index))
+}
+
+// the hashes of the nodes on the walk for key and the leaf at its end, if
+// the walk doesn't end at an empty child of the root
+fn walk_index(
+    root: &Root,
+    index: &HashMap<NodeHash, Node>,
+    key: &TrieKey,
+) -> Result<(Vec<NodeHash>, Option<Leaf>)> {
+    let mut path: Vec<NodeHash> = Vec::new();
+    let mut child_hash: Option<&NodeHash> = if key.bit(0) == 0 {
+        root.left.as_ref()
+    } else {
+        root.right.as_ref()
+    };
+    loop {
+        let Some(node_hash) = child_hash else {
+            return Ok((path, None));
+        };
+        let Some(node) = index.get(node_hash) else {
+            return Err(TrieError::InvalidProof("Proof is missing a node"));
+        };
+        path.push(node_hash.clone());
+        match node {
+            Node::Branch(branch) => {
+                let Some(split_idx) = branch.key.first() else {
+                    return Err(TrieError::InvalidBranch);
+                };
+                child_hash = if key.bit(*split_idx as usize) == 0 {
+                    branch.left.as_ref()
+                } else {
+                    branch.right.as_ref()
+                };
+                if child_hash.is_none() {
+                    return Err(TrieError::InvalidBranch);
+                }
+            }
+            Node::Leaf(leaf) => return Ok((path, Some(leaf.clone()))),
+            Node::Root(_) => return Err(TrieError::InvalidChild),
+        }
+    }
+}
+
+// collect the paths of all keys, the keys may or may not exist in the trie
+pub fn multi_proof(
+    db: &mut dyn Database,
+    keys: Vec<TrieKey>,
+    trie_root: Node,
+) -> Result<MultiProof> {
+    let root: Root = trie_root.unwrap_as_root()?;
+    let mut proof: MultiProof = MultiProof {
+        nodes: vec![Node::Root(root.clone())],
+        keys,
+    };
+    let mut seen: HashSet<NodeHash> = HashSet::new();
+    for key in &proof.keys {
+        let mut child_hash: Option<NodeHash> = if key.bit(0) == 0 {
+            root.left.clone()
+        } else {
+            root.right.clone()
+        };
+        while let Some(node_hash) = child_hash {
+            let node: Node = db.get_node(&node_hash)?;
+            child_hash = match &node {
+                Node::Branch(branch) => {
+                    if key.bit(*branch.key.first().ok_or(TrieError::InvalidBranch)? as usize) == 0 {
+                        branch.left.clone()
+                    } else {
+                        branch.right.clone()
+                    }
+                }
+                Node::Leaf(_) => None,
+                Node::Root(_) => return Err(TrieError::InvalidChild),
+            };
+            if seen.insert(node_hash) {
+                proof.nodes.push(node);
+            }
+        }
+    }
+    Ok(proof)
+}
+
+// returns Ok(false) if the proof does not match the state root hash and an
+// error if the path of any key is incomplete
+pub fn verify_multi_proof(proof: &MultiProof, state_root_hash: RootHash) -> Result<bool> {
+    Ok(proof.verify(state_root_hash)?.is_some())
+}
+
+// Proves that leaves holds every leaf with start <= key <= end. The nodes on
+// the walks for both bounds are included, every subtree between the walks is
+// rebuilt from the leaves by the verifier.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RangeProof {
+    pub start: TrieKey,
+    pub end: TrieKey,
+    // the Root followed by the nodes on the walks for start and end
+    pub nodes: Vec<Node>,
+    pub leaves: Vec<Leaf>,
+}
+
+pub fn range_proof(
+    db: &mut dyn Database,
+    start: TrieKey,
+    end: TrieKey,
+    trie_root: Node,
+) -> Result<RangeProof> {
+    if start > end {
+        return Err(TrieError::InvalidKey(
+            "the start of a range can't be after its end",
+        ));
+    }
+    let leaves: Vec<Leaf> =
+        range_scan(db, trie_root.clone(), Some(start), Some(end), usize::MAX)?.leaves;
+    let boundary: MultiProof = multi_proof(db, vec![start, end], trie_root)?;
+    Ok(RangeProof {
+        start,
+        end,
+        nodes: boundary.nodes,
+        leaves,
+    })
+}
+
+// returns Ok(false) if the leaves don't match the state root hash or are not
+// the complete range and an error if the proof is malformed
+pub fn verify_range_proof(proof: &RangeProof, state_root_hash: RootHash) -> Result<bool> {
+    let (start, end) = (proof.start, proof.end);
+    if start > end {
+        return Err(TrieError::InvalidProof(
+            "Range Proof must have start <= end",
+        ));
+    }
+    let sorted: bool = proof
+        .leaves
+        .windows(2)
+        .all(|pair| pair[0].key < pair[1].key);
+    let inside: bool = proof
+        .leaves
+        .iter()
+        .all(|leaf| start <= leaf.key && leaf.key <= end);
+    if !sorted || !inside {
+        return Err(TrieError::InvalidProof(
+            "Range Proof leaves must be sorted and inside the range",
+        ));
+    }
+    let (root, index) = index_nodes(&proof.nodes)?;
+    if root.hash != Some(state_root_hash) {
+        return Ok(false);
+    }
+    // every node on the walk for a bound shares its digits before the split
+    // index with the leaf at the end of the walk
+    let mut walk_keys: HashMap<NodeHash, TrieKey> = HashMap::new();
+    for bound in [start, end] {
+        let (path, leaf) = walk_index(&root, &index, &bound)?;
+        if let Some(leaf) = leaf {
+            for node_hash in path {
+                walk_keys.insert(node_hash, leaf.key);
+            }
+        }
+    }
+    let mut range_check = RangeCheck {
+        start,
+        end,
+        index: &index,
+        walk_keys: &walk_keys,
+        leaves: &proof.leaves,
+        used: 0,
+    };
+    for (digit, child_hash) in [(0, &root.left), (1, &root.right)] {
+        if let Some(child_hash) = child_hash {
+            let prefix: TrieKey = start.with_bit(0, digit);
+            if !range_check.check(child_hash, prefix, 1)? {
+                return Ok(false);
+            }
+        }
+    }
+    // leaves that are not in any subtree of the root are not in the trie
+    Ok(range_check.used == proof.leaves.len())
+}
+
+struct RangeCheck<'a> {
+    start: TrieKey,
+    end: TrieKey,
+    index: &'a HashMap<NodeHash, Node>,
+    walk_keys: &'a HashMap<NodeHash, TrieKey>,
+    leaves: &'a [Leaf],
+    // the number of leaves that were matched to the trie
+    used: usize,
+}
+
+impl RangeCheck<'_> {
+    // check the subtree at node_hash whose keys begin with the first
+    // prefix_bits digits of prefix
+    fn check(&mut self, node_hash: &NodeHash, prefix: TrieKey, prefix_bits: usize) -> Result<bool> {
+        if let (Some(node), Some(walk_key)) =
+            (self.index.get(node_hash), self.walk_keys.get(node_hash))
+        {
+            return match node {
+                Node::Branch(branch) => {
+                    let split_idx: usize =
+                        *branch.key.first().ok_or(TrieError::InvalidBranch)? as usize;
+                    for (digit, child_hash) in [(0, &branch.left), (1, &branch.right)] {
+                        let Some(child_hash) = child_hash else {
+                            return Err(TrieError::InvalidBranch);
+                        };
+                        let child_prefix: TrieKey = walk_key.with_bit(split_idx, digit);
+                        if !self.check(child_hash, child_prefix, split_idx + 1)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                Node::Leaf(leaf) => {
+                    if leaf.key < self.start || leaf.key > self.end {
+                        return Ok(true);
+                    }
+                    // a leaf at the end of a walk that is inside the range
+                    let idx: usize = self.leaves.partition_point(|other| other.key < leaf.key);
+                    let Some(other) = self.leaves.get(idx) else {
+                        return Ok(false);
+                    };
+                    let mut other: Leaf = other.clone();
+                    other.hash();
+                    self.used += 1;
+                    Ok(other.hash.as_ref() == Some(node_hash))
+                }
+                Node::Root(_) => Err(TrieError::InvalidChild),
+            };
+        }
+        // a subtree that hangs off the walks is either outside of the range or
+        // must be rebuilt from the leaves
+        let (lower, upper) = prefix.prefix_bounds(prefix_bits);
+        if upper < self.start || lower > self.end {
+            return Ok(true);
+        }
+        if lower < self.start || upper > self.end {
+            return Err(TrieError::InvalidProof(
+                "Range Proof is missing a boundary node",
+            ));
+        }
+        let first: usize = self.leaves.partition_point(|leaf| leaf.key < lower);
+        let last: usize = self.leaves.partition_point(|leaf| leaf.key <= upper);
+        if first == last {
+            return Ok(false);
+        }
+        self.used += last - first;
+        Ok(&subtree_hash(&self.leaves[first..last]) == node_hash)
+    }
+}
+
+// the hash of the subtree that holds exactly the given sorted leaves
+fn subtree_hash(leaves: &[Leaf]) -> NodeHash {
+    let (first, last) = (&leaves[0], &leaves[leaves.len() - 1]);
+    let Some(neq_idx) = first.key.first_diff(&last.key) else {
+        let mut leaf: Leaf = first.clone();
+        leaf.hash();
+        return leaf.hash.unwrap();
+    };
+    let split: usize = leaves.partition_point(|leaf| leaf.key.bit(neq_idx) == 0);
+    let mut branch: Branch = Branch::new(
+        vec![neq_idx as u8],
+        Some(subtree_hash(&leaves[..split])),
+        Some(subtree_hash(&leaves[split..])),
+    );
+    branch.hash();
+    branch.hash.unwrap()
+}
+
 #[cfg(test)]
 pub mod tests {
-    use crate::store::db::sql::TrieDB;
+    use crate::error::TrieError;
+    use crate::store::db::{memory::InMemoryDB, Database};
     use crate::{
         insert_leaf,
-        merkle::verify_merkle_proof,
-        store::types::{Hashable, Key, Leaf, Node, NodeHash, Root},
+        merkle::{
+            exclusion_proof, multi_proof, verify_compact_merkle_proof, verify_exclusion_proof,
+            verify_merkle_proof, verify_merkle_proof_for_key, verify_multi_proof,
+            verify_range_proof,
+        },
+        store::types::{Data, Hashable, Leaf, Node, Root, TrieKey},
+        trie::Trie,
+    };
+    use std::{
+        env,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Instant, SystemTime, UNIX_EPOCH},
     };
-    use std::{env, time::Instant};
 
     use super::merkle_proof;
     use colored::*;
 
     #[test]
     fn test_merkle_proof() {
-        let mut db = TrieDB {
-            path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-            cache: None,
-        };
-        db.setup();
-        let mut leaf_1: Leaf = Leaf::empty(vec![0u8; 256]);
+        let mut db = InMemoryDB::new();
+        let mut leaf_1: Leaf = Leaf::empty(TrieKey::new([0u8; 32]));
         leaf_1.hash();
 
         let mut leaf_2_key = vec![0, 0];
         for _i in 0..254 {
             leaf_2_key.push(1);
         }
-        let mut leaf_2: Leaf = Leaf::empty(leaf_2_key);
+        let mut leaf_2: Leaf = Leaf::empty(TrieKey::from_bits(&leaf_2_key).unwrap());
         leaf_2.hash();
         let root: Root = Root::empty();
         let root_node: Node = Node::Root(root);
@@ -133,79 +752,432 @@ pub mod tests {
                 .hash,
             leaf_2.hash
         );
-        verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap();
+        assert!(verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap());
 
         let proof = merkle_proof(&mut db, leaf_1.key, Node::Root(new_root.clone()));
         let inner_proof = proof.unwrap().nodes;
-        verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap();
+        assert!(verify_merkle_proof(inner_proof.clone(), new_root.hash.clone().unwrap()).unwrap());
+
+        // invalid proofs are rejected without panicking
+        assert!(!verify_merkle_proof(inner_proof.clone(), leaf_1.hash.clone().unwrap()).unwrap());
+        assert!(verify_merkle_proof(Vec::new(), new_root.hash.clone().unwrap()).is_err());
+        assert!(
+            verify_merkle_proof(inner_proof[1..].to_vec(), new_root.hash.clone().unwrap()).is_err()
+        );
+        let mut reordered_proof = inner_proof;
+        reordered_proof.swap(1, 2);
+        assert!(verify_merkle_proof(reordered_proof, new_root.hash.clone().unwrap()).is_err());
     }
 
     #[test]
-    fn simulate_insert_flow() {
-        let mut db = TrieDB {
-            path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-            cache: None,
+    fn test_merkle_proof_for_key() {
+        let mut db = InMemoryDB::new();
+        let mut leaf_1: Leaf = Leaf::new(TrieKey::new([0u8; 32]), Some(generate_random_data()));
+        leaf_1.hash();
+        let mut leaf_2_key = vec![0, 0];
+        for _i in 0..254 {
+            leaf_2_key.push(1);
+        }
+        let mut leaf_2: Leaf = Leaf::new(
+            TrieKey::from_bits(&leaf_2_key).unwrap(),
+            Some(generate_random_data()),
+        );
+        leaf_2.hash();
+        let root_node: Node = Node::Root(Root::empty());
+        let new_root: Root = insert_leaf(&mut db, &mut leaf_1, root_node).unwrap();
+        let new_root: Root = insert_leaf(&mut db, &mut leaf_2, Node::Root(new_root)).unwrap();
+        let root_hash = new_root.hash.clone().unwrap();
+        let inner_proof = merkle_proof(&mut db, leaf_2.key, Node::Root(new_root))
+            .unwrap()
+            .nodes;
+
+        assert!(verify_merkle_proof_for_key(
+            inner_proof.clone(),
+            &leaf_2.key,
+            None,
+            root_hash.clone()
+        )
+        .unwrap());
+        assert!(verify_merkle_proof_for_key(
+            inner_proof.clone(),
+            &leaf_2.key,
+            leaf_2.data.as_ref(),
+            root_hash.clone()
+        )
+        .unwrap());
+        // the proof authenticates leaf_2 but not the data or position of leaf_1
+        assert!(!verify_merkle_proof_for_key(
+            inner_proof.clone(),
+            &leaf_2.key,
+            leaf_1.data.as_ref(),
+            root_hash.clone()
+        )
+        .unwrap());
+        assert!(!verify_merkle_proof_for_key(
+            inner_proof.clone(),
+            &leaf_1.key,
+            None,
+            root_hash.clone()
+        )
+        .unwrap());
+        // direction flags that disagree with the key are rejected
+        let mut flipped_proof = inner_proof;
+        flipped_proof[1].0 = !flipped_proof[1].0;
+        assert!(!verify_merkle_proof_for_key(flipped_proof, &leaf_2.key, None, root_hash).unwrap());
+    }
+
+    #[test]
+    fn test_exclusion_proof() {
+        let mut db = InMemoryDB::new();
+        let mut leaf_1: Leaf = Leaf::empty(TrieKey::new([0u8; 32]));
+        leaf_1.hash();
+        let mut leaf_2_key = vec![0, 0];
+        for _i in 0..254 {
+            leaf_2_key.push(1);
+        }
+        let mut leaf_2: Leaf = Leaf::empty(TrieKey::from_bits(&leaf_2_key).unwrap());
+        leaf_2.hash();
+        let root_node: Node = Node::Root(Root::empty());
+        let new_root: Root = insert_leaf(&mut db, &mut leaf_1, root_node).unwrap();
+        let new_root: Root = insert_leaf(&mut db, &mut leaf_2, Node::Root(new_root)).unwrap();
+        let root_hash = new_root.hash.clone().unwrap();
+
+        // the walk ends at the empty right child of the root
+        let absent_key = TrieKey::new([0xff; 32]);
+        let proof = exclusion_proof(&mut db, absent_key, Node::Root(new_root.clone()));
+        let inner_proof = proof.unwrap().nodes;
+        assert_eq!(inner_proof.len(), 1);
+        assert!(verify_exclusion_proof(inner_proof, absent_key, root_hash.clone()).unwrap());
+
+        // the walk ends at leaf_1
+        let mut absent_key: Vec<u8> = vec![0u8; 256];
+        absent_key[255] = 1;
+        let absent_key = TrieKey::from_bits(&absent_key).unwrap();
+        let proof = exclusion_proof(&mut db, absent_key, Node::Root(new_root.clone()));
+        let inner_proof = proof.unwrap().nodes;
+        assert!(
+            verify_exclusion_proof(inner_proof.clone(), absent_key, root_hash.clone()).unwrap()
+        );
+        // a proof is bound to the state root and to the walk of its key
+        assert!(!verify_exclusion_proof(
+            inner_proof.clone(),
+            absent_key,
+            leaf_1.hash.clone().unwrap()
+        )
+        .unwrap());
+        assert!(!verify_exclusion_proof(inner_proof, leaf_1.key, root_hash.clone()).unwrap());
+        assert!(verify_exclusion_proof(Vec::new(), leaf_1.key, root_hash.clone()).is_err());
+
+        // present keys can't be excluded
+        assert!(exclusion_proof(&mut db, leaf_2.key, Node::Root(new_root.clone())).is_err());
+        let inclusion = merkle_proof(&mut db, leaf_2.key, Node::Root(new_root)).unwrap();
+        assert!(!verify_exclusion_proof(inclusion.nodes, leaf_2.key, root_hash).unwrap());
+    }
+
+    #[test]
+    fn test_compact_merkle_proof() {
+        let mut db = InMemoryDB::new();
+        let mut current_root: Root = Root::empty();
+        let mut leafs: Vec<Leaf> = Vec::new();
+        for _ in 0..16 {
+            let mut leaf: Leaf = Leaf::new(generate_random_key(), Some(generate_random_data()));
+            leaf.hash();
+            current_root = insert_leaf(&mut db, &mut leaf, Node::Root(current_root)).unwrap();
+            leafs.push(leaf);
+        }
+        let root_hash = current_root.hash.clone().unwrap();
+        for leaf in &leafs {
+            let proof = merkle_proof(&mut db, leaf.key, Node::Root(current_root.clone())).unwrap();
+            let compact_proof = proof.compact().unwrap();
+            assert_eq!(compact_proof.leaf, *leaf);
+            assert!(
+                bincode::serialize(&compact_proof).unwrap().len()
+                    < bincode::serialize(&proof).unwrap().len()
+            );
+            assert!(verify_compact_merkle_proof(compact_proof.clone(), root_hash.clone()).unwrap());
+
+            let mut tampered_proof = compact_proof;
+            tampered_proof.leaf.data = Some(generate_random_data());
+            assert!(!verify_compact_merkle_proof(tampered_proof, root_hash.clone()).unwrap());
+        }
+
+        // a branch without a key is rejected instead of panicking
+        let mut proof =
+            merkle_proof(&mut db, leafs[0].key, Node::Root(current_root.clone())).unwrap();
+        let (_, Node::Branch(branch)) = &mut proof.nodes[1] else {
+            panic!("the child of a root with 16 leafs is a branch");
         };
-        db.setup();
+        let branch_hash = branch.hash.clone().unwrap();
+        branch.key.clear();
+        assert!(matches!(proof.compact(), Err(TrieError::InvalidBranch)));
+        db.nodes.insert(branch_hash, proof.nodes[1].1.clone());
+        assert!(matches!(
+            merkle_proof(&mut db, leafs[0].key, Node::Root(current_root.clone())),
+            Err(TrieError::InvalidBranch)
+        ));
+        let absent_key: TrieKey = leafs[0].key.with_bit(255, 1 - leafs[0].key.bit(255));
+        assert!(matches!(
+            exclusion_proof(&mut db, absent_key, Node::Root(current_root)),
+            Err(TrieError::InvalidBranch)
+        ));
+    }
+
+    #[test]
+    fn simulate_insert_flow() {
+        let mut db = InMemoryDB::new();
         let root: Root = Root::empty();
         let root_node: Node = Node::Root(root);
         let mut current_root = root_node.clone();
-        let Message_count: u32 = env::var("STRESS_TEST_MESSAGE_COUNT")
+        let message_count: u32 = env::var("STRESS_TEST_MESSAGE_COUNT")
             .unwrap_or_else(|_| "1000".to_string())
             .parse::<u32>()
             .expect("Invalid argument STRESS_TEST_MESSAGE_COUNT");
-        let progress_bar: ProgressBar = ProgressBar::new(Message_count as u64);
+        let progress_bar: ProgressBar = ProgressBar::new(message_count as u64);
         let mut leafs: Vec<Leaf> = Vec::new();
-        for _ in 0..Message_count {
-            let leaf_key: Key = generate_random_key();
-            let mut leaf: Leaf = Leaf::empty(leaf_key.clone());
+        for _ in 0..message_count {
+            let leaf_key: TrieKey = generate_random_key();
+            let mut leaf: Leaf = Leaf::empty(leaf_key);
             leaf.data = Some(generate_random_data());
             leafs.push(leaf);
         }
-        let mut leaf_keys: Vec<NodeHash> = Vec::new();
+        let mut leaf_keys: Vec<TrieKey> = Vec::new();
         let start_time = Instant::now();
         for mut leaf in leafs {
             leaf.hash();
             let new_root: Root =
                 insert_leaf(&mut db, &mut leaf.clone(), current_root.clone()).unwrap();
-            let proof = merkle_proof(&mut db, leaf.key.clone(), Node::Root(new_root.clone()));
+            let proof = merkle_proof(&mut db, leaf.key, Node::Root(new_root.clone()));
             let inner_proof = proof.unwrap().nodes;
-            verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap();
+            assert!(verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap());
 
             #[cfg(feature = "stress-test")]
             for key in leaf_keys.clone() {
                 let proof = merkle_proof(&mut db, key, Node::Root(new_root.clone()));
-                let mut inner_proof = proof.unwrap().nodes;
-                verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap());
+                let inner_proof = proof.unwrap().nodes;
+                assert!(verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap());
             }
             #[cfg(not(feature = "stress-test"))]
             {
-                let proof = merkle_proof(&mut db, leaf.key.clone(), Node::Root(new_root.clone()));
+                let proof = merkle_proof(&mut db, leaf.key, Node::Root(new_root.clone()));
                 let inner_proof = proof.unwrap().nodes;
-                verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap();
+                assert!(verify_merkle_proof(inner_proof, new_root.hash.clone().unwrap()).unwrap());
             }
-            leaf_keys.push(leaf.key.clone());
+            leaf_keys.push(leaf.key);
             current_root = Node::Root(new_root.clone());
             progress_bar.inc(1);
         }
         progress_bar.finish_with_message("Done checking merkle proofs!");
         println!(
             "[{}x Merkle Proof] Elapsed Time: {} s",
-            Message_count.to_string().yellow(),
+            message_count.to_string().yellow(),
             &start_time.elapsed().as_secs().to_string().blue()
         );
     }
 
+    #[test]
+    fn test_multi_proof() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let keys: Vec<TrieKey> = (0..64).map(|_| generate_random_key()).collect();
+        for key in &keys {
+            trie.insert(*key, generate_random_data()).unwrap();
+        }
+        let root_hash = trie.root_hash();
+        let absent_keys: Vec<TrieKey> = (0..10).map(|_| generate_random_key()).collect();
+        let proven_keys: Vec<TrieKey> = keys[..20].iter().chain(&absent_keys).copied().collect();
+        let root = Node::Root(trie.root().clone());
+        let proof = multi_proof(&mut trie.db, proven_keys, root).unwrap();
+        assert!(verify_multi_proof(&proof, root_hash.clone()).unwrap());
+
+        // shared nodes are only included once
+        let single_proofs: usize = keys[..20]
+            .iter()
+            .map(|key| trie.prove(*key).unwrap().nodes.len())
+            .sum();
+        assert!(proof.nodes.len() < single_proofs);
+        let verified = proof.verify(root_hash.clone()).unwrap().unwrap();
+        for key in &keys[..20] {
+            assert_eq!(
+                verified.lookup(key).unwrap().unwrap().data,
+                trie.get(key).unwrap()
+            );
+        }
+        for key in &absent_keys {
+            assert_eq!(verified.lookup(key).unwrap(), None);
+        }
+
+        let mut other_trie = Trie::new(InMemoryDB::new()).unwrap();
+        other_trie.insert(keys[0], generate_random_data()).unwrap();
+        assert!(!verify_multi_proof(&proof, other_trie.root_hash()).unwrap());
+        assert!(proof.verify(other_trie.root_hash()).unwrap().is_none());
+        // a proof with a missing or modified node is rejected
+        let mut incomplete_proof = proof.clone();
+        incomplete_proof.nodes.pop();
+        assert!(verify_multi_proof(&incomplete_proof, root_hash.clone()).is_err());
+        let mut modified_proof = proof.clone();
+        let Some(Node::Leaf(leaf)) = modified_proof
+            .nodes
+            .iter_mut()
+            .find(|node| matches!(node, Node::Leaf(_)))
+        else {
+            panic!("Multi Proof has no leaf");
+        };
+        leaf.data = Some(generate_random_data());
+        assert!(verify_multi_proof(&modified_proof, root_hash).is_err());
+    }
+
+    #[test]
+    fn test_range_proof() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let mut keys: Vec<TrieKey> = (0..128).map(|_| generate_random_key()).collect();
+        for key in &keys {
+            trie.insert(*key, generate_random_data()).unwrap();
+        }
+        keys.sort();
+        let root_hash = trie.root_hash();
+        let mut bounds: Vec<(TrieKey, TrieKey)> = vec![
+            (keys[10], keys[90]),
+            (keys[0], keys[127]),
+            (keys[42], keys[42]),
+            (TrieKey::new([0u8; 32]), TrieKey::new([0xff; 32])),
+        ];
+        for _ in 0..16 {
+            let (a, b) = (generate_random_key(), generate_random_key());
+            bounds.push((a.min(b), a.max(b)));
+        }
+        for (start, end) in bounds {
+            let proof = trie.prove_range(start, end).unwrap();
+            let expected: Vec<TrieKey> = keys
+                .iter()
+                .copied()
+                .filter(|key| start <= *key && *key <= end)
+                .collect();
+            assert_eq!(
+                proof
+                    .leaves
+                    .iter()
+                    .map(|leaf| leaf.key)
+                    .collect::<Vec<TrieKey>>(),
+                expected
+            );
+            assert!(verify_range_proof(&proof, root_hash.clone()).unwrap());
+            if proof.leaves.is_empty() {
+                continue;
+            }
+            // omitting, changing or adding a leaf is detected
+            let mut omitted = proof.clone();
+            omitted.leaves.remove(omitted.leaves.len() / 2);
+            assert!(!verify_range_proof(&omitted, root_hash.clone()).unwrap_or(false));
+            let mut changed = proof.clone();
+            changed.leaves[0].data = Some(generate_random_data());
+            assert!(!verify_range_proof(&changed, root_hash.clone()).unwrap_or(false));
+            let mut added = proof.clone();
+            let fake_key = start.with_bit(255, 1 - start.bit(255));
+            if fake_key >= start && fake_key <= end && !keys.contains(&fake_key) {
+                let idx = added.leaves.partition_point(|leaf| leaf.key < fake_key);
+                added.leaves.insert(idx, Leaf::new(fake_key, None));
+                assert!(!verify_range_proof(&added, root_hash.clone()).unwrap_or(false));
+            }
+        }
+
+        let proof = trie.prove_range(keys[3], keys[7]).unwrap();
+        let mut other_trie = Trie::new(InMemoryDB::new()).unwrap();
+        other_trie.insert(keys[3], generate_random_data()).unwrap();
+        assert!(!verify_range_proof(&proof, other_trie.root_hash()).unwrap());
+        assert!(trie.prove_range(keys[7], keys[3]).is_err());
+        // the empty trie has no leaves in any range
+        let mut empty_trie = Trie::new(InMemoryDB::new()).unwrap();
+        let proof = empty_trie.prove_range(keys[3], keys[7]).unwrap();
+        assert!(proof.leaves.is_empty());
+        assert!(verify_range_proof(&proof, empty_trie.root_hash()).unwrap());
+    }
+
     use indicatif::ProgressBar;
     use rand::Rng;
-    pub fn generate_random_key() -> Key {
+    pub fn generate_random_key() -> TrieKey {
         let mut rng = rand::thread_rng();
-        (0..256)
-            .map(|_| if rng.gen_bool(0.5) { 1 } else { 0 })
-            .collect()
+        TrieKey::new(rng.gen())
     }
-    pub fn generate_random_data() -> Key {
+    pub fn generate_random_data() -> Data {
         let mut rng = rand::thread_rng();
         (0..256).map(|_| rng.gen_range(0..255)).collect()
     }
+
+    // counts the nodes that are loaded
+    #[derive(Default)]
+    pub struct CountingDB {
+        pub db: InMemoryDB,
+        pub reads: usize,
+    }
+
+    impl Database for CountingDB {
+        fn insert(&mut self, key: &[u8], node: Node) -> crate::error::Result<()> {
+            self.db.insert(key, node)
+        }
+        fn get(&mut self, key: &[u8]) -> crate::error::Result<Option<&mut Node>> {
+            self.reads += 1;
+            self.db.get(key)
+        }
+        fn remove(&mut self, key: &[u8]) -> crate::error::Result<()> {
+            self.db.remove(key)
+        }
+        fn node_keys(
+            &mut self,
+            after: Option<&[u8]>,
+            limit: usize,
+        ) -> crate::error::Result<Vec<Vec<u8>>> {
+            self.db.node_keys(after, limit)
+        }
+    }
+
+    // the number of nodes below the root on the path to key
+    pub fn path_len(db: &mut dyn Database, root: &Root, key: &TrieKey) -> usize {
+        let mut current_node: Node = Node::Root(root.clone());
+        let mut len: usize = 0;
+        loop {
+            let child_hash = match current_node {
+                Node::Leaf(_) => return len,
+                Node::Branch(branch) if key.bit(branch.key[0] as usize) == 0 => branch.left,
+                Node::Branch(branch) => branch.right,
+                Node::Root(root) if key.bit(0) == 0 => root.left,
+                Node::Root(root) => root.right,
+            };
+            current_node = db.get_node(&child_hash.unwrap()).unwrap();
+            len += 1;
+        }
+    }
+
+    // a unique file in the directory of PATH_TO_DB or in the temp dir, the
+    // file is removed on drop even if the test panics
+    pub struct TempPath(pub PathBuf);
+
+    impl TempPath {
+        pub fn new(name: &str) -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let dir: PathBuf = env::var("PATH_TO_DB")
+                .ok()
+                .and_then(|path| Path::new(&path).parent().map(Path::to_path_buf))
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(env::temp_dir);
+            let nanos: u128 = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0);
+            Self(dir.join(format!(
+                "{}-{}-{}-{}",
+                std::process::id(),
+                nanos,
+                COUNT.fetch_add(1, Ordering::Relaxed),
+                name
+            )))
+        }
+        pub fn to_str(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
 }
@@ -0,0 +1,199 @@
+// Range and prefix scans over a root, returned in pages. A page that doesn't
+// reach the end of the range carries a cursor to continue the scan later
+// against the same root hash.
+use crate::error::{Result, TrieError};
+use crate::iter::leaves_from;
+use crate::store::{
+    db::Database,
+    types::{Leaf, Node, Root, RootHash, TrieKey},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScanCursor {
+    root_hash: RootHash,
+    // the first key of the next page
+    next_key: TrieKey,
+    // the inclusive upper bound of the scan
+    end: Option<TrieKey>,
+}
+
+impl ScanCursor {
+    pub fn root_hash(&self) -> &RootHash {
+        &self.root_hash
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|_| TrieError::InvalidCursor)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScanPage {
+    pub leaves: Vec<Leaf>,
+    pub cursor: Option<ScanCursor>,
+}
+
+// at most limit leafs with start <= key <= end, missing bounds are unbounded
+pub fn range_scan(
+    db: &mut dyn Database,
+    root_node: Node,
+    start: Option<TrieKey>,
+    end: Option<TrieKey>,
+    limit: usize,
+) -> Result<ScanPage> {
+    let start: TrieKey = start.unwrap_or(TrieKey::new([0u8; 32]));
+    scan(db, root_node.unwrap_as_root()?, start, end, limit)
+}
+
+// at most limit leafs whose keys begin with the first prefix_bits digits of prefix
+pub fn prefix_scan(
+    db: &mut dyn Database,
+    root_node: Node,
+    prefix: &TrieKey,
+    prefix_bits: usize,
+    limit: usize,
+) -> Result<ScanPage> {
+    if prefix_bits > TrieKey::BITS {
+        return Err(TrieError::InvalidKey(
+            "a prefix can't have more than 256 digits",
+        ));
+    }
+    let (start, end) = prefix.prefix_bounds(prefix_bits);
+    scan(db, root_node.unwrap_as_root()?, start, Some(end), limit)
+}
+
+// the next page of the scan that returned the cursor
+pub fn resume_scan(db: &mut dyn Database, cursor: &ScanCursor, limit: usize) -> Result<ScanPage> {
+    let root: Root = db.get_node(&cursor.root_hash)?.unwrap_as_root()?;
+    scan(db, root, cursor.next_key, cursor.end, limit)
+}
+
+fn scan(
+    db: &mut dyn Database,
+    root: Root,
+    start: TrieKey,
+    end: Option<TrieKey>,
+    limit: usize,
+) -> Result<ScanPage> {
+    let Some(root_hash) = root.hash.clone() else {
+        return Err(TrieError::UnhashedNode);
+    };
+    let mut page: ScanPage = ScanPage {
+        leaves: Vec::new(),
+        cursor: None,
+    };
+    for leaf in leaves_from(db, Node::Root(root), &start)? {
+        let leaf: Leaf = leaf?;
+        if end.is_some_and(|end| leaf.key > end) {
+            break;
+        }
+        if page.leaves.len() == limit {
+            page.cursor = Some(ScanCursor {
+                root_hash,
+                next_key: leaf.key,
+                end,
+            });
+            break;
+        }
+        page.leaves.push(leaf);
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefix_scan, range_scan, resume_scan, ScanCursor};
+    use crate::error::TrieError;
+    use crate::iter::leaves_from;
+    use crate::merkle::tests::{generate_random_data, generate_random_key};
+    use crate::store::db::memory::InMemoryDB;
+    use crate::store::types::{Leaf, Node, TrieKey};
+    use crate::trie::Trie;
+
+    #[test]
+    fn test_range_scan() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let mut keys: Vec<TrieKey> = Vec::new();
+        // three tables with a 16 bit prefix each
+        for table in [[0x00, 0x01], [0x7f, 0xff], [0x80, 0x00]] {
+            for _ in 0..20 {
+                let mut bytes = *generate_random_key().as_bytes();
+                bytes[..2].copy_from_slice(&table);
+                keys.push(TrieKey::new(bytes));
+            }
+        }
+        for key in &keys {
+            trie.insert(*key, generate_random_data()).unwrap();
+        }
+        keys.sort();
+        let root = Node::Root(trie.root().clone());
+
+        // seeking to any key yields the same leafs as filtering the full list
+        for start in keys
+            .iter()
+            .copied()
+            .chain((0..20).map(|_| generate_random_key()))
+        {
+            let found: Vec<TrieKey> = leaves_from(&mut trie.db, root.clone(), &start)
+                .unwrap()
+                .map(|leaf| leaf.unwrap().key)
+                .collect();
+            let expected: Vec<TrieKey> = keys.iter().copied().filter(|key| *key >= start).collect();
+            assert_eq!(found, expected);
+        }
+
+        let page = range_scan(
+            &mut trie.db,
+            root.clone(),
+            Some(keys[5]),
+            Some(keys[30]),
+            100,
+        )
+        .unwrap();
+        assert_eq!(
+            page.leaves
+                .iter()
+                .map(|leaf| leaf.key)
+                .collect::<Vec<TrieKey>>(),
+            keys[5..=30]
+        );
+        assert!(page.cursor.is_none());
+
+        let mut prefix = [0u8; 32];
+        prefix[..2].copy_from_slice(&[0x7f, 0xff]);
+        let page = prefix_scan(&mut trie.db, root.clone(), &TrieKey::new(prefix), 16, 100).unwrap();
+        assert_eq!(
+            page.leaves
+                .iter()
+                .map(|leaf| leaf.key)
+                .collect::<Vec<TrieKey>>(),
+            keys[20..40]
+        );
+        assert!(matches!(
+            prefix_scan(&mut trie.db, root.clone(), &TrieKey::new(prefix), 257, 100),
+            Err(TrieError::InvalidKey(_))
+        ));
+
+        // page through the whole trie, later writes don't change the scanned root
+        let mut page = range_scan(&mut trie.db, root, None, None, 7).unwrap();
+        let mut found: Vec<Leaf> = page.leaves;
+        trie.remove(keys[10]).unwrap();
+        while let Some(cursor) = page.cursor {
+            let cursor = ScanCursor::from_bytes(&cursor.to_bytes()).unwrap();
+            page = resume_scan(&mut trie.db, &cursor, 7).unwrap();
+            assert!(page.leaves.len() <= 7);
+            found.extend(page.leaves);
+        }
+        assert_eq!(
+            found.iter().map(|leaf| leaf.key).collect::<Vec<TrieKey>>(),
+            keys
+        );
+        assert!(matches!(
+            ScanCursor::from_bytes(&[1, 2, 3]),
+            Err(TrieError::InvalidCursor)
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+// A trie addressed by arbitrary byte keys, e.g. account addresses or
+// strings. The path of a key is its sha256 hash, so keys are spread evenly
+// over the trie and don't have to be 256 bits long.
+use crate::error::{Result, TrieError};
+use crate::merkle::MerkleProof;
+use crate::store::{
+    db::Database,
+    types::{Data, RootHash, TrieKey},
+};
+use crate::trie::Trie;
+
+pub struct SecureTrie<D: Database> {
+    pub trie: Trie<D>,
+    // store the original key of every inserted leaf so that keys() can list them
+    pub record_preimages: bool,
+}
+
+impl<D: Database> SecureTrie<D> {
+    pub fn new(db: D) -> Result<Self> {
+        Ok(Self {
+            trie: Trie::new(db)?,
+            record_preimages: false,
+        })
+    }
+    pub fn with_preimages(db: D) -> Result<Self> {
+        let mut trie = Self::new(db)?;
+        trie.record_preimages = true;
+        Ok(trie)
+    }
+    pub fn open(db: D, root_hash: &[u8], record_preimages: bool) -> Result<Self> {
+        Ok(Self {
+            trie: Trie::open(db, root_hash)?,
+            record_preimages,
+        })
+    }
+    // the path at which the data of a key is stored
+    pub fn path(key: &[u8]) -> TrieKey {
+        TrieKey::sha256(key)
+    }
+    pub fn root_hash(&self) -> RootHash {
+        self.trie.root_hash()
+    }
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Data>> {
+        self.trie.get(&Self::path(key))
+    }
+    pub fn insert(&mut self, key: &[u8], data: Data) -> Result<RootHash> {
+        let path: TrieKey = Self::path(key);
+        // record the preimage first so that a backend without preimages
+        // fails before the trie changes
+        if self.record_preimages {
+            self.trie.db.insert_preimage(path.as_bytes(), key)?;
+        }
+        self.trie.insert(path, data)
+    }
+    pub fn update(&mut self, key: &[u8], data: Data) -> Result<RootHash> {
+        self.trie.update(Self::path(key), data)
+    }
+    // the preimage is kept, the key may still exist at an older root
+    pub fn delete(&mut self, key: &[u8]) -> Result<RootHash> {
+        self.trie.remove(Self::path(key))
+    }
+    // verify the proof with verify_merkle_proof_for_key at SecureTrie::path(key)
+    pub fn prove(&mut self, key: &[u8]) -> Result<MerkleProof> {
+        self.trie.prove(Self::path(key))
+    }
+    pub fn commit(&mut self) -> Result<RootHash> {
+        self.trie.commit()
+    }
+    pub fn rollback(&mut self) -> Result<()> {
+        self.trie.rollback()
+    }
+    // the original keys of all leafs, ordered by their path
+    pub fn keys(&mut self) -> Result<Vec<Data>> {
+        let paths: Vec<TrieKey> = self
+            .trie
+            .leaves()
+            .map(|leaf| leaf.map(|leaf| leaf.key))
+            .collect::<Result<_>>()?;
+        paths
+            .into_iter()
+            .map(|path| {
+                self.trie
+                    .db
+                    .get_preimage(path.as_bytes())?
+                    .ok_or(TrieError::MissingPreimage(path))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SecureTrie;
+    use crate::error::Result;
+    use crate::error::TrieError;
+    use crate::merkle::{tests::TempPath, verify_merkle_proof_for_key};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB, Database};
+    use crate::store::types::Node;
+
+    // a backend that only stores nodes
+    #[derive(Default)]
+    struct NodesOnly(InMemoryDB);
+    impl Database for NodesOnly {
+        fn insert(&mut self, key: &[u8], node: Node) -> Result<()> {
+            self.0.insert(key, node)
+        }
+        fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>> {
+            self.0.get(key)
+        }
+    }
+
+    #[test]
+    fn test_secure_trie() {
+        let mut trie = SecureTrie::with_preimages(InMemoryDB::new()).unwrap();
+        let empty_root = trie.root_hash();
+        trie.insert(b"alice", vec![1]).unwrap();
+        trie.insert(b"bob", vec![2]).unwrap();
+        let old_root = trie.insert(b"0x5a8c1f", vec![3]).unwrap();
+        assert_eq!(trie.get(b"alice").unwrap(), Some(vec![1]));
+        assert_eq!(trie.get(b"carol").unwrap(), None);
+        assert!(matches!(
+            trie.insert(b"bob", vec![4]),
+            Err(TrieError::DuplicateLeaf)
+        ));
+
+        trie.update(b"bob", vec![4]).unwrap();
+        assert_eq!(trie.get(b"bob").unwrap(), Some(vec![4]));
+        assert_ne!(trie.root_hash(), old_root);
+
+        let proof = trie.prove(b"bob").unwrap();
+        assert!(verify_merkle_proof_for_key(
+            proof.nodes.clone(),
+            &SecureTrie::<InMemoryDB>::path(b"bob"),
+            Some(&vec![4]),
+            trie.root_hash()
+        )
+        .unwrap());
+        assert!(!verify_merkle_proof_for_key(
+            proof.nodes,
+            &SecureTrie::<InMemoryDB>::path(b"alice"),
+            Some(&vec![4]),
+            trie.root_hash()
+        )
+        .unwrap_or(false));
+
+        let mut keys = trie.keys().unwrap();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![b"0x5a8c1f".to_vec(), b"alice".to_vec(), b"bob".to_vec()]
+        );
+
+        trie.delete(b"alice").unwrap();
+        trie.delete(b"bob").unwrap();
+        trie.delete(b"0x5a8c1f").unwrap();
+        assert_eq!(trie.root_hash(), empty_root);
+        assert!(trie.keys().unwrap().is_empty());
+        assert!(matches!(trie.delete(b"alice"), Err(TrieError::MissingLeaf)));
+
+        // without preimages the keys can't be listed
+        let mut trie = SecureTrie::new(InMemoryDB::new()).unwrap();
+        trie.insert(b"alice", vec![1]).unwrap();
+        assert!(matches!(trie.keys(), Err(TrieError::MissingPreimage(_))));
+
+        // a backend without preimages is rejected instead of dropping them
+        let mut trie = SecureTrie::with_preimages(NodesOnly::default()).unwrap();
+        let empty_root = trie.root_hash();
+        assert!(matches!(
+            trie.insert(b"alice", vec![1]),
+            Err(TrieError::Storage(_))
+        ));
+        assert_eq!(trie.root_hash(), empty_root);
+
+        let path = TempPath::new("secure_trie.sqlite");
+        let mut trie = SecureTrie::with_preimages(TrieDB::open(path.to_str()).unwrap()).unwrap();
+        trie.insert(b"alice", vec![1]).unwrap();
+        let root_hash = trie.commit().unwrap();
+        let mut trie = SecureTrie::open(trie.trie.db, &root_hash, true).unwrap();
+        assert_eq!(trie.keys().unwrap(), vec![b"alice".to_vec()]);
+    }
+}
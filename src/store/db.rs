@@ -1,61 +1,342 @@
-use crate::store::types::Node;
+use crate::error::{Result, TrieError};
+use crate::store::types::{Data, Node};
 pub trait Database {
-    fn insert(&mut self, key: &[u8], node: Node);
-    fn get(&mut self, key: &[u8]) -> Option<&mut Node>;
+    fn insert(&mut self, key: &[u8], node: Node) -> Result<()>;
+    fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>>;
+    // group the writes of one or more operations so that they are applied
+    // together, calls may be nested. Backends without transactions keep
+    // the default no-ops.
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn rollback(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // true if rollback() discards the writes since begin()
+    fn supports_transactions(&self) -> bool {
+        false
+    }
+    // called by Trie::commit with the root of the commit before the
+    // transaction is committed, e.g. to keep the root alive
+    fn commit_root(&mut self, _root_hash: &[u8]) -> Result<()> {
+        Ok(())
+    }
+    // like get, but a node that is not in the database is an error
+    fn get_node(&mut self, key: &[u8]) -> Result<Node> {
+        match self.get(key)? {
+            Some(node) => Ok(node.clone()),
+            None => Err(TrieError::MissingNode(key.to_vec())),
+        }
+    }
+    // delete a node, used by the pruner. Backends that can't delete keep the
+    // default and can't be pruned.
+    fn remove(&mut self, _key: &[u8]) -> Result<()> {
+        Err(TrieError::Storage(
+            "this database does not support removing nodes".to_string(),
+        ))
+    }
+    // at most limit node keys that are greater than after, in ascending order
+    fn node_keys(&mut self, _after: Option<&[u8]>, _limit: usize) -> Result<Vec<Vec<u8>>> {
+        Err(TrieError::Storage(
+            "this database does not support listing nodes".to_string(),
+        ))
+    }
+    // the original keys of a SecureTrie, indexed by their path. Backends
+    // that don't record preimages keep the defaults and can't be used with
+    // SecureTrie::with_preimages.
+    fn insert_preimage(&mut self, _path: &[u8], _preimage: &[u8]) -> Result<()> {
+        Err(TrieError::Storage(
+            "this database does not support preimages".to_string(),
+        ))
+    }
+    fn get_preimage(&mut self, _path: &[u8]) -> Result<Option<Data>> {
+        Ok(None)
+    }
 }
 
 pub mod sql {
     extern crate rusqlite;
     use super::Database;
-    use crate::store::types::Node;
-    use rusqlite::{params, Connection};
+    use crate::error::Result;
+    use crate::store::types::{Data, Node};
+    use rusqlite::{params, Connection, OptionalExtension};
 
     pub struct TrieDB {
         pub path: String,
         pub cache: Option<Node>,
+        pub(crate) conn: Connection,
     }
     impl TrieDB {
-        pub fn setup(&self) {
-            let conn = Connection::open(&self.path).expect("Unhandled Error: SQL Connection");
-            conn.execute(
+        // open the database at path and create the nodes table if needed
+        pub fn open(path: &str) -> Result<Self> {
+            let db = Self {
+                path: path.to_string(),
+                cache: None,
+                conn: Connection::open(path)?,
+            };
+            db.setup()?;
+            Ok(db)
+        }
+        pub fn setup(&self) -> Result<()> {
+            self.conn.execute(
                 "CREATE TABLE IF NOT EXISTS nodes (
                           key    BLOB PRIMARY KEY,
                           node   BLOB NOT NULL
                           )",
                 [],
-            )
-            .expect("Unhandled Error: SQL Insert");
+            )?;
+            self.conn.execute(
+                "CREATE TABLE IF NOT EXISTS refcounts (
+                          key    BLOB PRIMARY KEY,
+                          count  INTEGER NOT NULL
+                          )",
+                [],
+            )?;
+            self.conn.execute(
+                "CREATE TABLE IF NOT EXISTS roots (
+                          version   INTEGER PRIMARY KEY,
+                          root_hash BLOB NOT NULL,
+                          timestamp INTEGER NOT NULL,
+                          metadata  BLOB NOT NULL
+                          )",
+                [],
+            )?;
+            self.conn.execute(
+                "CREATE TABLE IF NOT EXISTS refs (
+                          name      TEXT PRIMARY KEY,
+                          root_hash BLOB NOT NULL
+                          )",
+                [],
+            )?;
+            self.conn.execute(
+                "CREATE TABLE IF NOT EXISTS preimages (
+                          path     BLOB PRIMARY KEY,
+                          preimage BLOB NOT NULL
+                          )",
+                [],
+            )?;
+            Ok(())
         }
     }
     impl Database for TrieDB {
-        fn insert(&mut self, key: &[u8], node: Node) {
-            let conn = Connection::open(&self.path).expect("Unhandled Error: SQL Connection");
-            conn.execute(
-                "INSERT OR REPLACE INTO nodes (key, node) VALUES (?1, ?2)",
-                params![key, bincode::serialize(&node).unwrap()],
-            )
-            .expect("Unhandled Error: SQL Insert");
-        }
-        fn get(&mut self, key: &[u8]) -> Option<&mut Node> {
-            let conn = Connection::open(&self.path).unwrap();
-            let mut stmt = conn
-                .prepare("SELECT node FROM nodes WHERE key = ?1 LIMIT 1")
-                .expect("Unhandled Error: SQL Connection");
-
-            let node_serialized: Option<Vec<u8>> = stmt
-                .query_row([&key], |row| {
-                    let node_serialized: Vec<u8> = row.get(0).unwrap();
-                    Ok(Some(node_serialized))
-                })
-                .unwrap_or(None);
+        fn insert(&mut self, key: &[u8], node: Node) -> Result<()> {
+            self.conn
+                .prepare_cached("INSERT OR REPLACE INTO nodes (key, node) VALUES (?1, ?2)")?
+                .execute(params![key, bincode::serialize(&node)?])?;
+            Ok(())
+        }
+        fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>> {
+            let node_serialized: Option<Vec<u8>> = self
+                .conn
+                .prepare_cached("SELECT node FROM nodes WHERE key = ?1 LIMIT 1")?
+                .query_row([&key], |row| row.get(0))
+                .optional()?;
 
             if let Some(node_serialized) = node_serialized {
-                let node: Node = bincode::deserialize(&node_serialized).unwrap();
+                let node: Node = bincode::deserialize(&node_serialized)?;
                 self.cache = Some(node);
-                self.cache.as_mut()
+                Ok(self.cache.as_mut())
             } else {
-                None
+                Ok(None)
             }
         }
+        // savepoints nest, the outermost one opens and commits the transaction
+        fn begin(&mut self) -> Result<()> {
+            self.conn.execute_batch("SAVEPOINT trie")?;
+            Ok(())
+        }
+        fn commit(&mut self) -> Result<()> {
+            self.conn.execute_batch("RELEASE trie")?;
+            Ok(())
+        }
+        fn rollback(&mut self) -> Result<()> {
+            self.conn.execute_batch("ROLLBACK TO trie; RELEASE trie")?;
+            Ok(())
+        }
+        fn supports_transactions(&self) -> bool {
+            true
+        }
+        fn remove(&mut self, key: &[u8]) -> Result<()> {
+            self.conn
+                .prepare_cached("DELETE FROM nodes WHERE key = ?1")?
+                .execute([key])?;
+            Ok(())
+        }
+        fn node_keys(&mut self, after: Option<&[u8]>, limit: usize) -> Result<Vec<Vec<u8>>> {
+            let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+            let keys = match after {
+                Some(after) => self
+                    .conn
+                    .prepare_cached("SELECT key FROM nodes WHERE key > ?1 ORDER BY key LIMIT ?2")?
+                    .query_map(params![after, limit], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?,
+                None => self
+                    .conn
+                    .prepare_cached("SELECT key FROM nodes ORDER BY key LIMIT ?1")?
+                    .query_map([limit], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?,
+            };
+            Ok(keys)
+        }
+        fn insert_preimage(&mut self, path: &[u8], preimage: &[u8]) -> Result<()> {
+            self.conn
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO preimages (path, preimage) VALUES (?1, ?2)",
+                )?
+                .execute(params![path, preimage])?;
+            Ok(())
+        }
+        fn get_preimage(&mut self, path: &[u8]) -> Result<Option<Data>> {
+            Ok(self
+                .conn
+                .prepare_cached("SELECT preimage FROM preimages WHERE path = ?1 LIMIT 1")?
+                .query_row([&path], |row| row.get(0))
+                .optional()?)
+        }
+    }
+}
+
+pub mod memory {
+    use super::Database;
+    use crate::error::Result;
+    use crate::store::registry::RootRecord;
+    use crate::store::types::{Data, Node, RootHash};
+    use std::collections::{BTreeMap, HashMap};
+    use std::ops::Bound;
+
+    // the previous value of an entry that was written in a transaction
+    pub(crate) enum Undo {
+        Node(Vec<u8>, Option<Node>),
+        Preimage(Vec<u8>, Option<Data>),
+        RefCount(Vec<u8>, Option<u64>),
+        Root(u64, Option<RootRecord>),
+        Ref(String, Option<RootHash>),
+    }
+
+    // keeps all nodes in a BTreeMap ordered by key, useful for tests and
+    // scratch tries. Transactions work like the savepoints of TrieDB, writes
+    // through the Database and store traits are undone by rollback().
+    #[derive(Default)]
+    pub struct InMemoryDB {
+        pub nodes: BTreeMap<Vec<u8>, Node>,
+        pub preimages: HashMap<Vec<u8>, Data>,
+        pub ref_counts: HashMap<Vec<u8>, u64>,
+        pub roots: BTreeMap<u64, RootRecord>,
+        pub refs: BTreeMap<String, RootHash>,
+        pub cache: Option<Node>,
+        undo_log: Vec<Undo>,
+        // the length of the undo log at each open begin()
+        savepoints: Vec<usize>,
+    }
+    impl InMemoryDB {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        // remember the previous value of an entry, only needed inside a
+        // transaction
+        pub(crate) fn record(&mut self, undo: Undo) {
+            if !self.savepoints.is_empty() {
+                self.undo_log.push(undo);
+            }
+        }
+        fn undo(&mut self, undo: Undo) {
+            match undo {
+                Undo::Node(key, Some(node)) => {
+                    self.nodes.insert(key, node);
+                }
+                Undo::Node(key, None) => {
+                    self.nodes.remove(&key);
+                }
+                Undo::Preimage(path, Some(preimage)) => {
+                    self.preimages.insert(path, preimage);
+                }
+                Undo::Preimage(path, None) => {
+                    self.preimages.remove(&path);
+                }
+                Undo::RefCount(key, Some(count)) => {
+                    self.ref_counts.insert(key, count);
+                }
+                Undo::RefCount(key, None) => {
+                    self.ref_counts.remove(&key);
+                }
+                Undo::Root(version, Some(record)) => {
+                    self.roots.insert(version, record);
+                }
+                Undo::Root(version, None) => {
+                    self.roots.remove(&version);
+                }
+                Undo::Ref(name, Some(root_hash)) => {
+                    self.refs.insert(name, root_hash);
+                }
+                Undo::Ref(name, None) => {
+                    self.refs.remove(&name);
+                }
+            }
+        }
+    }
+    impl Database for InMemoryDB {
+        fn insert(&mut self, key: &[u8], node: Node) -> Result<()> {
+            let old: Option<Node> = self.nodes.insert(key.to_vec(), node);
+            self.record(Undo::Node(key.to_vec(), old));
+            Ok(())
+        }
+        // like TrieDB this returns a copy, changes to it are not persisted
+        fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>> {
+            self.cache = self.nodes.get(key).cloned();
+            Ok(self.cache.as_mut())
+        }
+        fn begin(&mut self) -> Result<()> {
+            self.savepoints.push(self.undo_log.len());
+            Ok(())
+        }
+        // the outermost commit drops the undo log, inner ones keep it for a
+        // rollback of the outer transaction
+        fn commit(&mut self) -> Result<()> {
+            self.savepoints.pop();
+            if self.savepoints.is_empty() {
+                self.undo_log.clear();
+            }
+            Ok(())
+        }
+        fn rollback(&mut self) -> Result<()> {
+            let start: usize = self.savepoints.pop().unwrap_or(0);
+            while self.undo_log.len() > start {
+                let undo: Undo = self.undo_log.pop().unwrap();
+                self.undo(undo);
+            }
+            Ok(())
+        }
+        fn supports_transactions(&self) -> bool {
+            true
+        }
+        fn remove(&mut self, key: &[u8]) -> Result<()> {
+            if let Some(old) = self.nodes.remove(key) {
+                self.record(Undo::Node(key.to_vec(), Some(old)));
+            }
+            Ok(())
+        }
+        fn node_keys(&mut self, after: Option<&[u8]>, limit: usize) -> Result<Vec<Vec<u8>>> {
+            let lower: Bound<&[u8]> = match after {
+                Some(after) => Bound::Excluded(after),
+                None => Bound::Unbounded,
+            };
+            Ok(self
+                .nodes
+                .range::<[u8], _>((lower, Bound::Unbounded))
+                .take(limit)
+                .map(|(key, _)| key.clone())
+                .collect())
+        }
+        fn insert_preimage(&mut self, path: &[u8], preimage: &[u8]) -> Result<()> {
+            let old: Option<Data> = self.preimages.insert(path.to_vec(), preimage.to_vec());
+            self.record(Undo::Preimage(path.to_vec(), old));
+            Ok(())
+        }
+        fn get_preimage(&mut self, path: &[u8]) -> Result<Option<Data>> {
+            Ok(self.preimages.get(path).cloned())
+        }
     }
 }
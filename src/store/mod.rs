@@ -1,2 +1,7 @@
 pub mod db;
+pub mod prune;
+pub mod refcount;
+pub mod refs;
+pub mod registry;
+pub mod snapshot;
 pub mod types;
@@ -0,0 +1,266 @@
+// Incremental mark and sweep over the nodes table. The pruner first marks every
+// node reachable from the retained roots and then deletes all other nodes, both
+// phases run in steps of a bounded number of nodes so that inserts can continue
+// between steps.
+//
+// A root that is created while pruning must be passed to retain() before the
+// next step, otherwise its new nodes may be swept. Inserts must only build on
+// retained roots.
+use super::db::Database;
+use super::types::{Node, NodeHash, RootHash};
+use crate::error::{Result, TrieError};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrunePhase {
+    Mark,
+    Sweep,
+    Done,
+}
+
+pub struct Pruner {
+    // nodes reachable from the retained roots
+    marked: HashSet<NodeHash>,
+    // nodes that are reachable but whose children were not visited yet
+    pending: Vec<NodeHash>,
+    // the last key the sweep has checked
+    sweep_cursor: Option<Vec<u8>>,
+    phase: PrunePhase,
+    // the number of nodes deleted so far
+    pub removed: usize,
+}
+
+impl Pruner {
+    pub fn new(keep: Vec<RootHash>) -> Self {
+        Self {
+            marked: HashSet::new(),
+            pending: keep,
+            sweep_cursor: None,
+            phase: PrunePhase::Mark,
+            removed: 0,
+        }
+    }
+    pub fn phase(&self) -> PrunePhase {
+        self.phase
+    }
+    // keep a root that was created after the pruner started. While marking
+    // the root is queued for the next steps, during the sweep its nodes are
+    // marked right away, only its new nodes are unmarked by then.
+    pub fn retain(&mut self, db: &mut dyn Database, root_hash: RootHash) -> Result<()> {
+        match self.phase {
+            PrunePhase::Mark => self.pending.push(root_hash),
+            PrunePhase::Sweep => {
+                let mut pending: Vec<NodeHash> = vec![root_hash];
+                while let Some(node_hash) = pending.pop() {
+                    self.visit(db, node_hash, &mut pending)?;
+                }
+            }
+            PrunePhase::Done => {}
+        }
+        Ok(())
+    }
+    // mark the node and queue its unmarked children, nodes that are already
+    // marked were visited before
+    fn visit(
+        &mut self,
+        db: &mut dyn Database,
+        node_hash: NodeHash,
+        pending: &mut Vec<NodeHash>,
+    ) -> Result<()> {
+        if self.marked.contains(&node_hash) {
+            return Ok(());
+        }
+        let (left, right) = match db.get_node(&node_hash)? {
+            Node::Root(root) => (root.left, root.right),
+            Node::Branch(branch) => (branch.left, branch.right),
+            Node::Leaf(_) => (None, None),
+        };
+        for child_hash in [left, right].into_iter().flatten() {
+            if !self.marked.contains(&child_hash) {
+                pending.push(child_hash);
+            }
+        }
+        self.marked.insert(node_hash);
+        Ok(())
+    }
+    // visit or sweep at most budget nodes, returns true once pruning is done
+    pub fn step(&mut self, db: &mut dyn Database, budget: usize) -> Result<bool> {
+        // a step without budget would never move on to the next phase
+        if budget == 0 {
+            return Err(TrieError::InvalidBudget);
+        }
+        match self.phase {
+            PrunePhase::Mark => {
+                let mut pending: Vec<NodeHash> = std::mem::take(&mut self.pending);
+                for _ in 0..budget {
+                    let Some(node_hash) = pending.pop() else {
+                        break;
+                    };
+                    self.visit(db, node_hash, &mut pending)?;
+                }
+                if pending.is_empty() {
+                    self.phase = PrunePhase::Sweep;
+                }
+                self.pending = pending;
+            }
+            PrunePhase::Sweep => {
+                let keys: Vec<Vec<u8>> = db.node_keys(self.sweep_cursor.as_deref(), budget)?;
+                db.begin()?;
+                for key in &keys {
+                    if !self.marked.contains(key) {
+                        if let Err(e) = db.remove(key) {
+                            db.rollback()?;
+                            return Err(e);
+                        }
+                        self.removed += 1;
+                    }
+                }
+                db.commit()?;
+                if keys.len() < budget {
+                    self.phase = PrunePhase::Done;
+                }
+                self.sweep_cursor = keys.last().cloned().or(self.sweep_cursor.take());
+            }
+            PrunePhase::Done => {}
+        }
+        Ok(self.phase == PrunePhase::Done)
+    }
+    // prune to completion and return the number of deleted nodes
+    pub fn run(&mut self, db: &mut dyn Database) -> Result<usize> {
+        while !self.step(db, 1024)? {}
+        Ok(self.removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PrunePhase, Pruner};
+    use crate::error::TrieError;
+    use crate::merkle::tests::{
+        generate_random_data, generate_random_key, path_len, CountingDB, TempPath,
+    };
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB, Database};
+    use crate::store::types::{RootHash, TrieKey};
+    use crate::trie::Trie;
+
+    fn build_history<D: Database>(trie: &mut Trie<D>, keys: &mut Vec<TrieKey>) -> Vec<RootHash> {
+        let mut roots: Vec<RootHash> = Vec::new();
+        for _ in 0..8 {
+            for _ in 0..8 {
+                let key = generate_random_key();
+                trie.insert(key, generate_random_data()).unwrap();
+                keys.push(key);
+            }
+            trie.update(keys[0], generate_random_data()).unwrap();
+            roots.push(trie.commit().unwrap());
+        }
+        roots
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let mut keys: Vec<TrieKey> = Vec::new();
+        let roots = build_history(&mut trie, &mut keys);
+        let node_count = trie.db.nodes.len();
+
+        // keep the last two roots and insert against the latest while pruning
+        let mut pruner = Pruner::new(roots[6..].to_vec());
+        let mut new_roots: Vec<RootHash> = Vec::new();
+        let mut new_nodes: usize = 0;
+        while !pruner.step(&mut trie.db, 16).unwrap() {
+            let key = generate_random_key();
+            let nodes_before = trie.db.nodes.len();
+            let root_hash = trie.insert(key, generate_random_data()).unwrap();
+            new_nodes += trie.db.nodes.len() - nodes_before;
+            pruner.retain(&mut trie.db, root_hash.clone()).unwrap();
+            keys.push(key);
+            new_roots.push(root_hash);
+        }
+        assert!(pruner.removed > 0);
+        assert!(matches!(
+            pruner.step(&mut trie.db, 0),
+            Err(TrieError::InvalidBudget)
+        ));
+        assert_eq!(trie.db.nodes.len(), node_count + new_nodes - pruner.removed);
+
+        // the retained roots are complete, older roots are gone
+        let db = &mut trie.db;
+        for root_hash in roots[6..].iter().chain(&new_roots) {
+            let mut retained = Trie::open(std::mem::take(db), root_hash).unwrap();
+            assert!(retained.leaves().all(|leaf| leaf.is_ok()));
+            *db = retained.db;
+        }
+        assert!(matches!(
+            Trie::open(std::mem::take(db), &roots[0]),
+            Err(TrieError::MissingNode(_))
+        ));
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut trie = Trie::new(CountingDB::default()).unwrap();
+        for _ in 0..512 {
+            trie.insert(generate_random_key(), generate_random_data())
+                .unwrap();
+        }
+        let mut roots: Vec<RootHash> = vec![trie.commit().unwrap()];
+        let mut pruner = Pruner::new(roots.clone());
+        pruner.step(&mut trie.db, 16).unwrap();
+
+        // while marking a new root is only queued
+        roots.push(
+            trie.insert(generate_random_key(), generate_random_data())
+                .unwrap(),
+        );
+        trie.db.reads = 0;
+        pruner
+            .retain(&mut trie.db, roots.last().unwrap().clone())
+            .unwrap();
+        assert_eq!(trie.db.reads, 0);
+        assert_eq!(pruner.phase(), PrunePhase::Mark);
+
+        // during the sweep only the new nodes are read
+        while pruner.phase() == PrunePhase::Mark {
+            pruner.step(&mut trie.db, 16).unwrap();
+        }
+        let key: TrieKey = generate_random_key();
+        roots.push(trie.insert(key, generate_random_data()).unwrap());
+        let root = trie.root().clone();
+        let new_nodes: usize = path_len(&mut trie.db, &root, &key) + 1;
+        trie.db.reads = 0;
+        pruner
+            .retain(&mut trie.db, roots.last().unwrap().clone())
+            .unwrap();
+        assert!(trie.db.reads <= new_nodes);
+
+        while !pruner.step(&mut trie.db, 16).unwrap() {}
+        let db = &mut trie.db;
+        for root_hash in &roots {
+            let mut retained = Trie::open(std::mem::take(db), root_hash).unwrap();
+            assert!(retained.leaves().all(|leaf| leaf.is_ok()));
+            *db = retained.db;
+        }
+    }
+
+    #[test]
+    fn test_prune_sql() {
+        let path = TempPath::new("prune.sqlite");
+        let mut trie = Trie::new(TrieDB::open(path.to_str()).unwrap()).unwrap();
+        let mut keys: Vec<TrieKey> = Vec::new();
+        let roots = build_history(&mut trie, &mut keys);
+        let removed = Pruner::new(vec![roots[7].clone()])
+            .run(&mut trie.db)
+            .unwrap();
+        assert!(removed > 0);
+        assert_eq!(
+            Pruner::new(vec![roots[7].clone()])
+                .run(&mut trie.db)
+                .unwrap(),
+            0
+        );
+        let mut trie = Trie::open(trie.db, &roots[7]).unwrap();
+        assert_eq!(trie.leaves().count(), keys.len());
+        assert!(Trie::open(trie.db, &roots[6]).is_err());
+    }
+}
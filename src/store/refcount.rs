@@ -0,0 +1,372 @@
+// Reference counted node storage. The first write of a node increments the
+// count of each of its children and every commit holds its root, so the count
+// of a node is the number of stored parents plus the holds on a root. Nodes
+// that were written since the last commit but are not reachable from its root,
+// e.g. the roots of single inserts, are deleted by the commit, and so are
+// the nodes of a rollback on backends without transactions. Releasing a root
+// deletes every node whose count drops to 0.
+//
+// Committing a root that is already held doesn't add another hold, use hold()
+// to keep a root for more than one owner.
+use super::db::{
+    memory::{InMemoryDB, Undo},
+    sql::TrieDB,
+    Database,
+};
+use super::types::{Data, Node, NodeHash, RootHash};
+use crate::error::{Result, TrieError};
+use rusqlite::{params, OptionalExtension};
+
+// storage for the reference counts of RefCounted, a count of 0 is not stored
+pub trait RefCountStore: Database {
+    fn get_ref_count(&mut self, key: &[u8]) -> Result<u64>;
+    fn set_ref_count(&mut self, key: &[u8], count: u64) -> Result<()>;
+}
+
+impl RefCountStore for TrieDB {
+    fn get_ref_count(&mut self, key: &[u8]) -> Result<u64> {
+        let count: Option<i64> = self
+            .conn
+            .prepare_cached("SELECT count FROM refcounts WHERE key = ?1 LIMIT 1")?
+            .query_row([key], |row| row.get(0))
+            .optional()?;
+        Ok(count.unwrap_or(0) as u64)
+    }
+    fn set_ref_count(&mut self, key: &[u8], count: u64) -> Result<()> {
+        if count == 0 {
+            self.conn
+                .prepare_cached("DELETE FROM refcounts WHERE key = ?1")?
+                .execute([key])?;
+        } else {
+            self.conn
+                .prepare_cached("INSERT OR REPLACE INTO refcounts (key, count) VALUES (?1, ?2)")?
+                .execute(params![key, count as i64])?;
+        }
+        Ok(())
+    }
+}
+
+impl RefCountStore for InMemoryDB {
+    fn get_ref_count(&mut self, key: &[u8]) -> Result<u64> {
+        Ok(self.ref_counts.get(key).copied().unwrap_or(0))
+    }
+    fn set_ref_count(&mut self, key: &[u8], count: u64) -> Result<()> {
+        let old: Option<u64> = if count == 0 {
+            self.ref_counts.remove(key)
+        } else {
+            self.ref_counts.insert(key.to_vec(), count)
+        };
+        self.record(Undo::RefCount(key.to_vec(), old));
+        Ok(())
+    }
+}
+
+pub struct RefCounted<D: RefCountStore> {
+    pub db: D,
+    // nodes written since the last commit
+    fresh: Vec<NodeHash>,
+}
+
+impl<D: RefCountStore> RefCounted<D> {
+    pub fn new(db: D) -> Self {
+        Self {
+            db,
+            fresh: Vec::new(),
+        }
+    }
+    pub fn ref_count(&mut self, key: &[u8]) -> Result<u64> {
+        self.db.get_ref_count(key)
+    }
+    fn increment(&mut self, key: &[u8]) -> Result<()> {
+        let count: u64 = self.db.get_ref_count(key)?;
+        self.db.set_ref_count(key, count + 1)
+    }
+    // add a hold on a stored root
+    pub fn hold(&mut self, root_hash: &[u8]) -> Result<()> {
+        self.db.get_node(root_hash)?.unwrap_as_root()?;
+        self.increment(root_hash)
+    }
+    // drop a hold on a root and delete the nodes that are no longer
+    // reachable, returns the number of deleted nodes
+    pub fn release(&mut self, root_hash: &RootHash) -> Result<usize> {
+        self.db.begin()?;
+        match self.release_nodes(root_hash) {
+            Ok(removed) => {
+                self.db.commit()?;
+                Ok(removed)
+            }
+            Err(e) => {
+                self.db.rollback()?;
+                Err(e)
+            }
+        }
+    }
+    fn release_nodes(&mut self, root_hash: &RootHash) -> Result<usize> {
+        self.db.get_node(root_hash)?.unwrap_as_root()?;
+        let count: u64 = self.db.get_ref_count(root_hash)?;
+        if count == 0 {
+            return Err(TrieError::MissingNode(root_hash.clone()));
+        }
+        self.db.set_ref_count(root_hash, count - 1)?;
+        if count > 1 {
+            return Ok(0);
+        }
+        self.delete_nodes(root_hash)
+    }
+    // delete the nodes written since the last commit that no parent or hold
+    // refers to, nodes that were rolled back are gone already
+    fn delete_fresh(&mut self) -> Result<()> {
+        for node_hash in std::mem::take(&mut self.fresh) {
+            if self.db.get_ref_count(&node_hash)? == 0 && self.db.get(&node_hash)?.is_some() {
+                self.delete_nodes(&node_hash)?;
+            }
+        }
+        Ok(())
+    }
+    // delete a node with a count of 0 and every child that is left without
+    // a parent
+    fn delete_nodes(&mut self, node_hash: &[u8]) -> Result<usize> {
+        let mut removed: usize = 0;
+        let mut pending: Vec<NodeHash> = vec![node_hash.to_vec()];
+        while let Some(node_hash) = pending.pop() {
+            let (left, right) = match self.db.get_node(&node_hash)? {
+                Node::Root(root) => (root.left, root.right),
+                Node::Branch(branch) => (branch.left, branch.right),
+                Node::Leaf(_) => (None, None),
+            };
+            self.db.remove(&node_hash)?;
+            removed += 1;
+            for child_hash in [left, right].into_iter().flatten() {
+                let count: u64 = self.db.get_ref_count(&child_hash)?;
+                if count == 0 {
+                    return Err(TrieError::MissingNode(child_hash));
+                }
+                self.db.set_ref_count(&child_hash, count - 1)?;
+                if count == 1 {
+                    pending.push(child_hash);
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl<D: RefCountStore> Database for RefCounted<D> {
+    fn insert(&mut self, key: &[u8], node: Node) -> Result<()> {
+        // a node that is already stored was counted by its parents before
+        if self.db.get(key)?.is_some() {
+            return Ok(());
+        }
+        let (left, right) = match &node {
+            Node::Root(root) => (root.left.clone(), root.right.clone()),
+            Node::Branch(branch) => (branch.left.clone(), branch.right.clone()),
+            Node::Leaf(_) => (None, None),
+        };
+        self.db.insert(key, node)?;
+        for child_hash in [left, right].into_iter().flatten() {
+            self.increment(&child_hash)?;
+        }
+        self.fresh.push(key.to_vec());
+        Ok(())
+    }
+    fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>> {
+        self.db.get(key)
+    }
+    fn begin(&mut self) -> Result<()> {
+        self.db.begin()
+    }
+    fn commit(&mut self) -> Result<()> {
+        self.db.commit()
+    }
+    // a backend without transactions keeps the discarded nodes, they are
+    // deleted here like the unreachable nodes of a commit
+    fn rollback(&mut self) -> Result<()> {
+        self.db.rollback()?;
+        if !self.db.supports_transactions() {
+            self.delete_fresh()?;
+        }
+        Ok(())
+    }
+    fn supports_transactions(&self) -> bool {
+        self.db.supports_transactions()
+    }
+    // hold the root unless it is held already and delete the fresh nodes
+    // that no parent refers to
+    fn commit_root(&mut self, root_hash: &[u8]) -> Result<()> {
+        if self.db.get_ref_count(root_hash)? == 0 {
+            self.hold(root_hash)?;
+        }
+        self.delete_fresh()
+    }
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        self.db.remove(key)
+    }
+    fn node_keys(&mut self, after: Option<&[u8]>, limit: usize) -> Result<Vec<Vec<u8>>> {
+        self.db.node_keys(after, limit)
+    }
+    fn insert_preimage(&mut self, path: &[u8], preimage: &[u8]) -> Result<()> {
+        self.db.insert_preimage(path, preimage)
+    }
+    fn get_preimage(&mut self, path: &[u8]) -> Result<Option<Data>> {
+        self.db.get_preimage(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RefCountStore, RefCounted};
+    use crate::error::Result;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, TempPath};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB, Database};
+    use crate::store::types::{Data, Node, RootHash, TrieKey};
+    use crate::trie::Trie;
+
+    #[test]
+    fn test_ref_counted() {
+        let mut trie = Trie::new(RefCounted::new(InMemoryDB::new())).unwrap();
+        let empty_root: RootHash = trie.root_hash();
+        let mut entries: Vec<(TrieKey, Data)> = Vec::new();
+        for _ in 0..64 {
+            let (key, data) = (generate_random_key(), generate_random_data());
+            trie.insert(key, data.clone()).unwrap();
+            entries.push((key, data));
+        }
+        let old_root: RootHash = trie.commit().unwrap();
+        let data = generate_random_data();
+        trie.update(entries[5].0, data.clone()).unwrap();
+        entries[5].1 = data;
+        trie.remove(entries[9].0).unwrap();
+        entries.remove(9);
+        let latest: RootHash = trie.commit().unwrap();
+        // committing the same root again doesn't add a hold
+        trie.commit().unwrap();
+        assert_eq!(trie.db.ref_count(&latest).unwrap(), 1);
+
+        // the latest root shares most of its nodes with the old one
+        assert_eq!(trie.db.release(&empty_root).unwrap(), 1);
+        assert!(trie.db.release(&old_root).unwrap() > 0);
+        assert!(matches!(
+            trie.db.release(&old_root),
+            Err(TrieError::MissingNode(_))
+        ));
+
+        // only the nodes of the latest root are left
+        let mut fresh = Trie::new(InMemoryDB::new()).unwrap();
+        fresh.insert_batch(entries.clone()).unwrap();
+        assert_eq!(fresh.root_hash(), latest);
+        assert_eq!(trie.db.db.nodes.len(), fresh.db.nodes.len() - 1);
+        assert_eq!(trie.leaves().count(), entries.len());
+        assert!(trie.leaves().all(|leaf| leaf.is_ok()));
+
+        // a second hold keeps the root after the first release
+        trie.db.hold(&latest).unwrap();
+        trie.db.release(&latest).unwrap();
+        assert_eq!(trie.get(&entries[0].0).unwrap(), Some(entries[0].1.clone()));
+        assert_eq!(trie.db.release(&latest).unwrap(), fresh.db.nodes.len() - 1);
+        assert!(trie.db.db.nodes.is_empty());
+        assert!(trie.db.db.ref_counts.is_empty());
+    }
+
+    #[test]
+    fn test_ref_counted_commit() {
+        let mut trie = Trie::new(RefCounted::new(InMemoryDB::new())).unwrap();
+        let empty_root: RootHash = trie.root_hash();
+        for _ in 0..64 {
+            trie.insert(generate_random_key(), generate_random_data())
+                .unwrap();
+        }
+        let root_hash: RootHash = trie.commit().unwrap();
+        // the roots and branches of the single inserts are deleted by the
+        // commit, the empty root, 64 leaves and 63 inner nodes are left
+        assert_eq!(trie.db.db.nodes.len(), 1 + 2 * 64 - 1);
+        trie.db.release(&empty_root).unwrap();
+        assert_eq!(trie.db.release(&root_hash).unwrap(), 2 * 64 - 1);
+        assert!(trie.db.db.nodes.is_empty());
+        assert!(trie.db.db.ref_counts.is_empty());
+    }
+
+    // a backend without transactions
+    #[derive(Default)]
+    struct NoTransactions(InMemoryDB);
+    impl Database for NoTransactions {
+        fn insert(&mut self, key: &[u8], node: Node) -> Result<()> {
+            self.0.insert(key, node)
+        }
+        fn get(&mut self, key: &[u8]) -> Result<Option<&mut Node>> {
+            self.0.get(key)
+        }
+        fn remove(&mut self, key: &[u8]) -> Result<()> {
+            self.0.remove(key)
+        }
+        fn node_keys(&mut self, after: Option<&[u8]>, limit: usize) -> Result<Vec<Vec<u8>>> {
+            self.0.node_keys(after, limit)
+        }
+    }
+    impl RefCountStore for NoTransactions {
+        fn get_ref_count(&mut self, key: &[u8]) -> Result<u64> {
+            self.0.get_ref_count(key)
+        }
+        fn set_ref_count(&mut self, key: &[u8], count: u64) -> Result<()> {
+            self.0.set_ref_count(key, count)
+        }
+    }
+
+    fn check_rollback<D: RefCountStore>(db: D) {
+        let mut trie = Trie::new(RefCounted::new(db)).unwrap();
+        let empty_root: RootHash = trie.root_hash();
+        trie.insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        trie.insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        let root_hash: RootHash = trie.commit().unwrap();
+        let nodes: Vec<Vec<u8>> = trie.db.node_keys(None, usize::MAX).unwrap();
+        let discarded_root: RootHash = trie
+            .insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        trie.rollback().unwrap();
+        trie.commit().unwrap();
+        assert!(trie.db.get(&discarded_root).unwrap().is_none());
+        assert_eq!(trie.db.node_keys(None, usize::MAX).unwrap(), nodes);
+        // the counts of the discarded writes are gone as well
+        trie.db.release(&empty_root).unwrap();
+        trie.db.release(&root_hash).unwrap();
+        assert!(trie.db.node_keys(None, 1).unwrap().is_empty());
+        assert_eq!(trie.db.ref_count(&nodes[0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_ref_counted_rollback() {
+        check_rollback(NoTransactions::default());
+        check_rollback(InMemoryDB::new());
+    }
+
+    #[test]
+    fn test_ref_counted_sql() {
+        let path = TempPath::new("refcount.sqlite");
+        let db = RefCounted::new(TrieDB::open(path.to_str()).unwrap());
+        let mut trie = Trie::new(db).unwrap();
+        let empty_root: RootHash = trie.root_hash();
+        let key = generate_random_key();
+        trie.insert(key, generate_random_data()).unwrap();
+        trie.insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        let old_root: RootHash = trie.commit().unwrap();
+        trie.update(key, generate_random_data()).unwrap();
+        let new_root: RootHash = trie.commit().unwrap();
+        // a rolled back write leaves no nodes behind
+        let discarded_root: RootHash = trie
+            .insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        trie.rollback().unwrap();
+        trie.commit().unwrap();
+        assert!(trie.db.get(&discarded_root).unwrap().is_none());
+
+        trie.db.release(&empty_root).unwrap();
+        // the old root loses its leaf for key and the shared leaf stays
+        trie.db.release(&old_root).unwrap();
+        let mut trie = Trie::open(trie.db, &new_root).unwrap();
+        assert_eq!(trie.leaves().count(), 2);
+        assert!(Trie::open(trie.db, &old_root).is_err());
+    }
+}
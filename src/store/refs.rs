@@ -0,0 +1,223 @@
+// Named references to stored roots, e.g. "finalized", "head" or "proposal-42".
+// A ref can be moved with compare_and_swap_ref() so that concurrent writers
+// only advance it from the root they have seen. Refs don't keep their nodes
+// alive, pass the referenced roots to the Pruner before pruning.
+use super::db::{
+    memory::{InMemoryDB, Undo},
+    sql::TrieDB,
+    Database,
+};
+use super::refcount::{RefCountStore, RefCounted};
+use super::types::RootHash;
+use crate::error::Result;
+use rusqlite::{params, OptionalExtension};
+
+pub trait RefStore: Database {
+    fn get_ref(&mut self, name: &str) -> Result<Option<RootHash>>;
+    // all refs ordered by name
+    fn list_refs(&mut self) -> Result<Vec<(String, RootHash)>>;
+    // point name at root_hash or delete the ref, without any checks
+    fn write_ref(&mut self, name: &str, root_hash: Option<&[u8]>) -> Result<()>;
+
+    // point name at a stored root
+    fn set_ref(&mut self, name: &str, root_hash: &[u8]) -> Result<()> {
+        self.get_node(root_hash)?.unwrap_as_root()?;
+        self.write_ref(name, Some(root_hash))
+    }
+    // returns true if the ref existed
+    fn delete_ref(&mut self, name: &str) -> Result<bool> {
+        let exists: bool = self.get_ref(name)?.is_some();
+        self.write_ref(name, None)?;
+        Ok(exists)
+    }
+    // move name from expected to new, where None means the ref doesn't exist.
+    // Returns false and leaves the ref unchanged if it doesn't point at
+    // expected.
+    fn compare_and_swap_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        if let Some(root_hash) = new {
+            self.get_node(root_hash)?.unwrap_as_root()?;
+        }
+        self.begin()?;
+        let swapped: Result<bool> = self.get_ref(name).and_then(|current| {
+            if current.as_deref() != expected {
+                return Ok(false);
+            }
+            self.write_ref(name, new)?;
+            Ok(true)
+        });
+        match swapped {
+            Ok(swapped) => {
+                self.commit()?;
+                Ok(swapped)
+            }
+            Err(e) => {
+                self.rollback()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl RefStore for InMemoryDB {
+    fn get_ref(&mut self, name: &str) -> Result<Option<RootHash>> {
+        Ok(self.refs.get(name).cloned())
+    }
+    fn list_refs(&mut self) -> Result<Vec<(String, RootHash)>> {
+        Ok(self
+            .refs
+            .iter()
+            .map(|(name, root_hash)| (name.clone(), root_hash.clone()))
+            .collect())
+    }
+    fn write_ref(&mut self, name: &str, root_hash: Option<&[u8]>) -> Result<()> {
+        let old: Option<RootHash> = match root_hash {
+            Some(root_hash) => self.refs.insert(name.to_string(), root_hash.to_vec()),
+            None => self.refs.remove(name),
+        };
+        self.record(Undo::Ref(name.to_string(), old));
+        Ok(())
+    }
+}
+
+impl RefStore for TrieDB {
+    fn get_ref(&mut self, name: &str) -> Result<Option<RootHash>> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT root_hash FROM refs WHERE name = ?1")?
+            .query_row([name], |row| row.get(0))
+            .optional()?)
+    }
+    fn list_refs(&mut self) -> Result<Vec<(String, RootHash)>> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT name, root_hash FROM refs ORDER BY name")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, RootHash)>>>()?)
+    }
+    fn write_ref(&mut self, name: &str, root_hash: Option<&[u8]>) -> Result<()> {
+        match root_hash {
+            Some(root_hash) => self
+                .conn
+                .prepare_cached("INSERT OR REPLACE INTO refs (name, root_hash) VALUES (?1, ?2)")?
+                .execute(params![name, root_hash])?,
+            None => self
+                .conn
+                .prepare_cached("DELETE FROM refs WHERE name = ?1")?
+                .execute([name])?,
+        };
+        Ok(())
+    }
+}
+
+impl<D: RefStore + RefCountStore> RefStore for RefCounted<D> {
+    fn get_ref(&mut self, name: &str) -> Result<Option<RootHash>> {
+        self.db.get_ref(name)
+    }
+    fn list_refs(&mut self) -> Result<Vec<(String, RootHash)>> {
+        self.db.list_refs()
+    }
+    fn write_ref(&mut self, name: &str, root_hash: Option<&[u8]>) -> Result<()> {
+        self.db.write_ref(name, root_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RefStore;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, TempPath};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB};
+    use crate::store::prune::Pruner;
+    use crate::store::types::{RootHash, TrieKey};
+    use crate::trie::Trie;
+
+    fn check_refs<D: RefStore>(mut trie: Trie<D>) {
+        let key: TrieKey = generate_random_key();
+        trie.insert(key, generate_random_data()).unwrap();
+        let finalized: RootHash = trie.commit().unwrap();
+        trie.db.set_ref("finalized", &finalized).unwrap();
+        trie.db.set_ref("head", &finalized).unwrap();
+        assert!(matches!(
+            trie.db.set_ref("head", &[0u8; 32]),
+            Err(TrieError::MissingNode(_))
+        ));
+
+        // build a proposal on top of the finalized root and advance head
+        let mut proposal = Trie::open_ref(trie.db, "finalized").unwrap();
+        let proposal_key: TrieKey = generate_random_key();
+        proposal
+            .insert(proposal_key, generate_random_data())
+            .unwrap();
+        let proposal_root: RootHash = proposal.commit().unwrap();
+        proposal.db.set_ref("proposal-42", &proposal_root).unwrap();
+        assert!(proposal
+            .db
+            .compare_and_swap_ref("head", Some(&finalized), Some(&proposal_root))
+            .unwrap());
+        // a second writer that saw the old head loses
+        assert!(!proposal
+            .db
+            .compare_and_swap_ref("head", Some(&finalized), Some(&finalized))
+            .unwrap());
+        assert!(!proposal
+            .db
+            .compare_and_swap_ref("finalized", None, Some(&proposal_root))
+            .unwrap());
+        assert_eq!(
+            proposal.db.list_refs().unwrap(),
+            vec![
+                ("finalized".to_string(), finalized.clone()),
+                ("head".to_string(), proposal_root.clone()),
+                ("proposal-42".to_string(), proposal_root.clone()),
+            ]
+        );
+
+        // discard the proposal, the finalized ref is unchanged
+        assert!(proposal
+            .db
+            .compare_and_swap_ref("head", Some(&proposal_root), Some(&finalized))
+            .unwrap());
+        assert!(proposal.db.delete_ref("proposal-42").unwrap());
+        assert!(!proposal.db.delete_ref("proposal-42").unwrap());
+        let mut head = Trie::open_ref(proposal.db, "head").unwrap();
+        assert!(head.get(&key).unwrap().is_some());
+        assert!(head.get(&proposal_key).unwrap().is_none());
+        assert!(matches!(
+            Trie::open_ref(head.db, "proposal-42"),
+            Err(TrieError::MissingRef(_))
+        ));
+    }
+
+    #[test]
+    fn test_refs() {
+        check_refs(Trie::new(InMemoryDB::new()).unwrap());
+        let path = TempPath::new("refs.sqlite");
+        check_refs(Trie::new(TrieDB::open(path.to_str()).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_prune_refs() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let finalized: RootHash = trie
+            .insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        trie.db.set_ref("finalized", &finalized).unwrap();
+        trie.insert(generate_random_key(), generate_random_data())
+            .unwrap();
+        let keep: Vec<RootHash> = trie
+            .db
+            .list_refs()
+            .unwrap()
+            .into_iter()
+            .map(|(_, root_hash)| root_hash)
+            .collect();
+        assert!(Pruner::new(keep).run(&mut trie.db).unwrap() > 0);
+        let mut finalized_trie = Trie::open_ref(trie.db, "finalized").unwrap();
+        assert_eq!(finalized_trie.leaves().count(), 1);
+    }
+}
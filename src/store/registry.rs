@@ -0,0 +1,247 @@
+// A registry of committed roots. Every root is recorded at a version, e.g. a
+// block height, that must be greater than the version of the latest root,
+// together with the time of the registration and caller supplied metadata.
+// Versions are limited to i64::MAX so that every backend can store them.
+use super::db::{
+    memory::{InMemoryDB, Undo},
+    sql::TrieDB,
+    Database,
+};
+use super::refcount::{RefCountStore, RefCounted};
+use super::types::{Data, RootHash};
+use crate::error::{Result, TrieError};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RootRecord {
+    pub version: u64,
+    pub root_hash: RootHash,
+    // seconds since the unix epoch
+    pub timestamp: u64,
+    pub metadata: Data,
+}
+
+pub trait RootRegistry: Database {
+    fn insert_root_record(&mut self, record: &RootRecord) -> Result<()>;
+    fn root_at(&mut self, version: u64) -> Result<Option<RootRecord>>;
+    fn latest_root(&mut self) -> Result<Option<RootRecord>>;
+    // the records with from <= version <= to in ascending order
+    fn roots_in_range(&mut self, from: u64, to: u64) -> Result<Vec<RootRecord>>;
+
+    // record a stored root at the given version
+    fn register_root(
+        &mut self,
+        version: u64,
+        root_hash: &[u8],
+        metadata: &[u8],
+    ) -> Result<RootRecord> {
+        self.get_node(root_hash)?.unwrap_as_root()?;
+        if i64::try_from(version).is_err() {
+            return Err(TrieError::InvalidVersion(version));
+        }
+        if let Some(latest) = self.latest_root()? {
+            if version <= latest.version {
+                return Err(TrieError::InvalidVersion(version));
+            }
+        }
+        let record: RootRecord = RootRecord {
+            version,
+            root_hash: root_hash.to_vec(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            metadata: metadata.to_vec(),
+        };
+        self.insert_root_record(&record)?;
+        Ok(record)
+    }
+}
+
+impl RootRegistry for InMemoryDB {
+    fn insert_root_record(&mut self, record: &RootRecord) -> Result<()> {
+        let old: Option<RootRecord> = self.roots.insert(record.version, record.clone());
+        self.record(Undo::Root(record.version, old));
+        Ok(())
+    }
+    fn root_at(&mut self, version: u64) -> Result<Option<RootRecord>> {
+        Ok(self.roots.get(&version).cloned())
+    }
+    fn latest_root(&mut self) -> Result<Option<RootRecord>> {
+        Ok(self.roots.values().next_back().cloned())
+    }
+    fn roots_in_range(&mut self, from: u64, to: u64) -> Result<Vec<RootRecord>> {
+        if from > to {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .roots
+            .range(from..=to)
+            .map(|(_, record)| record.clone())
+            .collect())
+    }
+}
+
+// versions and timestamps are stored as INTEGER, which SQLite limits to i64
+fn version_to_sql(version: u64) -> Result<i64> {
+    version
+        .try_into()
+        .map_err(|_| TrieError::InvalidVersion(version))
+}
+
+fn read_record(row: &rusqlite::Row) -> rusqlite::Result<RootRecord> {
+    Ok(RootRecord {
+        version: row.get::<_, i64>(0)? as u64,
+        root_hash: row.get(1)?,
+        timestamp: row.get::<_, i64>(2)? as u64,
+        metadata: row.get(3)?,
+    })
+}
+
+impl RootRegistry for TrieDB {
+    fn insert_root_record(&mut self, record: &RootRecord) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO roots (version, root_hash, timestamp, metadata) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![
+                version_to_sql(record.version)?,
+                record.root_hash,
+                i64::try_from(record.timestamp).unwrap_or(i64::MAX),
+                record.metadata
+            ])?;
+        Ok(())
+    }
+    fn root_at(&mut self, version: u64) -> Result<Option<RootRecord>> {
+        let Ok(version) = version_to_sql(version) else {
+            return Ok(None);
+        };
+        Ok(self
+            .conn
+            .prepare_cached(
+                "SELECT version, root_hash, timestamp, metadata FROM roots WHERE version = ?1",
+            )?
+            .query_row([version], read_record)
+            .optional()?)
+    }
+    fn latest_root(&mut self) -> Result<Option<RootRecord>> {
+        Ok(self
+            .conn
+            .prepare_cached(
+                "SELECT version, root_hash, timestamp, metadata FROM roots ORDER BY version DESC LIMIT 1",
+            )?
+            .query_row([], read_record)
+            .optional()?)
+    }
+    fn roots_in_range(&mut self, from: u64, to: u64) -> Result<Vec<RootRecord>> {
+        // no version is above i64::MAX, so a larger end is the same as i64::MAX
+        let Ok(from) = version_to_sql(from) else {
+            return Ok(Vec::new());
+        };
+        let to: i64 = version_to_sql(to).unwrap_or(i64::MAX);
+        Ok(self
+            .conn
+            .prepare_cached(
+                "SELECT version, root_hash, timestamp, metadata FROM roots
+                 WHERE version >= ?1 AND version <= ?2 ORDER BY version",
+            )?
+            .query_map([from, to], read_record)?
+            .collect::<rusqlite::Result<Vec<RootRecord>>>()?)
+    }
+}
+
+impl<D: RootRegistry + RefCountStore> RootRegistry for RefCounted<D> {
+    fn insert_root_record(&mut self, record: &RootRecord) -> Result<()> {
+        self.db.insert_root_record(record)
+    }
+    fn root_at(&mut self, version: u64) -> Result<Option<RootRecord>> {
+        self.db.root_at(version)
+    }
+    fn latest_root(&mut self) -> Result<Option<RootRecord>> {
+        self.db.latest_root()
+    }
+    fn roots_in_range(&mut self, from: u64, to: u64) -> Result<Vec<RootRecord>> {
+        self.db.roots_in_range(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RootRegistry;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, TempPath};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB};
+    use crate::store::types::TrieKey;
+    use crate::trie::Trie;
+
+    fn check_registry<D: RootRegistry>(mut trie: Trie<D>) {
+        assert!(trie.db.latest_root().unwrap().is_none());
+        let keys: Vec<TrieKey> = (0..5).map(|_| generate_random_key()).collect();
+        for (idx, key) in keys.iter().enumerate() {
+            trie.insert(*key, generate_random_data()).unwrap();
+            let version: u64 = 100 + 10 * idx as u64;
+            let record = trie
+                .commit_version(version, format!("block {}", version).as_bytes())
+                .unwrap();
+            assert_eq!(record.root_hash, trie.root_hash());
+        }
+        let latest = trie.db.latest_root().unwrap().unwrap();
+        assert_eq!(latest.version, 140);
+        assert_eq!(latest.metadata, b"block 140".to_vec());
+        assert_eq!(latest.root_hash, trie.root_hash());
+        assert!(trie.db.root_at(105).unwrap().is_none());
+        let records = trie.db.roots_in_range(105, 130).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.version)
+                .collect::<Vec<u64>>(),
+            vec![110, 120, 130]
+        );
+        assert!(trie.db.roots_in_range(130, 105).unwrap().is_empty());
+
+        // versions only increase and only stored roots can be registered
+        let root_hash = trie.root_hash();
+        assert!(matches!(
+            trie.db.register_root(140, &root_hash, &[]),
+            Err(TrieError::InvalidVersion(140))
+        ));
+        assert!(matches!(
+            trie.db.register_root(150, &[0u8; 32], &[]),
+            Err(TrieError::MissingNode(_))
+        ));
+        // versions above i64::MAX are rejected instead of being clamped
+        assert!(matches!(
+            trie.db.register_root(u64::MAX, &root_hash, &[]),
+            Err(TrieError::InvalidVersion(u64::MAX))
+        ));
+        assert!(matches!(
+            trie.db.register_root(i64::MAX as u64 + 1, &root_hash, &[]),
+            Err(TrieError::InvalidVersion(_))
+        ));
+        assert!(trie.db.root_at(u64::MAX).unwrap().is_none());
+        assert_eq!(trie.db.roots_in_range(135, u64::MAX).unwrap().len(), 1);
+        assert!(trie
+            .db
+            .roots_in_range(u64::MAX, u64::MAX)
+            .unwrap()
+            .is_empty());
+
+        let mut old_trie = Trie::open_version(trie.db, 110).unwrap();
+        assert!(old_trie.get(&keys[1]).unwrap().is_some());
+        assert!(old_trie.get(&keys[2]).unwrap().is_none());
+        assert!(matches!(
+            Trie::open_version(old_trie.db, 105),
+            Err(TrieError::MissingVersion(105))
+        ));
+    }
+
+    #[test]
+    fn test_root_registry() {
+        check_registry(Trie::new(InMemoryDB::new()).unwrap());
+        let path = TempPath::new("registry.sqlite");
+        check_registry(Trie::new(TrieDB::open(path.to_str()).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,311 @@
+// Snapshot files of the state at one root. A snapshot starts with the magic
+// bytes and the format version, followed by the bincode encoded header and
+// every node reachable from the root, parents before their children and left
+// before right. Each node is written as its encoded length (u32, little
+// endian) and its bincode encoding, the checksum in the header is the sha256
+// hash of these node records.
+//
+// Import recomputes every node hash and checks that each node is the next
+// node of the walk below the root in the header, so the nodes of a snapshot
+// always match that root hash. The root hash itself comes from the file and
+// must be compared with a trusted root by the caller. Backends with
+// transactions receive the nodes while they are read and only keep the
+// hashes on the path to the current node, other backends get the nodes after
+// the whole snapshot was verified, which keeps every node in memory.
+use super::db::Database;
+use super::types::{Hashable, Node, NodeHash, RootHash};
+use crate::error::{Result, TrieError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"PTRIESNP";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub root_hash: RootHash,
+    pub node_count: u64,
+    pub checksum: Vec<u8>,
+}
+
+// visit every node below root_hash, parents before their children
+fn walk(
+    db: &mut dyn Database,
+    root_hash: &[u8],
+    mut f: impl FnMut(&Node) -> Result<()>,
+) -> Result<()> {
+    db.get_node(root_hash)?.unwrap_as_root()?;
+    let mut pending: Vec<NodeHash> = vec![root_hash.to_vec()];
+    while let Some(node_hash) = pending.pop() {
+        let node: Node = db.get_node(&node_hash)?;
+        match &node {
+            Node::Root(root) => {
+                pending.extend(root.right.clone());
+                pending.extend(root.left.clone());
+            }
+            Node::Branch(branch) => {
+                pending.extend(branch.right.clone());
+                pending.extend(branch.left.clone());
+            }
+            Node::Leaf(_) => {}
+        }
+        f(&node)?;
+    }
+    Ok(())
+}
+
+fn node_record(node: &Node) -> Result<Vec<u8>> {
+    let encoded: Vec<u8> = bincode::serialize(node)?;
+    let len: u32 = encoded
+        .len()
+        .try_into()
+        .map_err(|_| TrieError::Storage("node is too large for a snapshot".to_string()))?;
+    let mut record: Vec<u8> = len.to_le_bytes().to_vec();
+    record.extend(encoded);
+    Ok(record)
+}
+
+// write every node reachable from the root, the nodes are read twice so that
+// the header can be written before them without buffering the whole state
+pub fn export_snapshot(
+    db: &mut dyn Database,
+    root_hash: &[u8],
+    writer: &mut dyn Write,
+) -> Result<SnapshotHeader> {
+    let mut hasher = Sha256::new();
+    let mut node_count: u64 = 0;
+    walk(db, root_hash, |node| {
+        hasher.update(node_record(node)?);
+        node_count += 1;
+        Ok(())
+    })?;
+    let header: SnapshotHeader = SnapshotHeader {
+        root_hash: root_hash.to_vec(),
+        node_count,
+        checksum: hasher.finalize().to_vec(),
+    };
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&bincode::serialize(&header)?)?;
+    walk(db, root_hash, |node| {
+        Ok(writer.write_all(&node_record(node)?)?)
+    })?;
+    writer.flush()?;
+    Ok(header)
+}
+
+// read exactly len bytes without trusting len for the allocation
+fn read_bytes(reader: &mut dyn Read, len: u64) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(TrieError::InvalidSnapshot("Snapshot is truncated"));
+    }
+    Ok(bytes)
+}
+
+fn read_header(reader: &mut dyn Read) -> Result<SnapshotHeader> {
+    if read_bytes(reader, 8)? != SNAPSHOT_MAGIC {
+        return Err(TrieError::InvalidSnapshot("Not a snapshot file"));
+    }
+    let version: [u8; 4] = read_bytes(reader, 4)?.try_into().unwrap();
+    if u32::from_le_bytes(version) != SNAPSHOT_VERSION {
+        return Err(TrieError::InvalidSnapshot("Unsupported format version"));
+    }
+    bincode::deserialize_from(reader)
+        .map_err(|_| TrieError::InvalidSnapshot("Invalid snapshot header"))
+}
+
+// read the nodes of a snapshot in the order of walk() and pass each node to
+// f once its hash has been checked, the whole snapshot is verified when this
+// returns Ok
+fn read_nodes(
+    reader: &mut dyn Read,
+    header: &SnapshotHeader,
+    mut f: impl FnMut(NodeHash, Node) -> Result<()>,
+) -> Result<()> {
+    let mut hasher = Sha256::new();
+    // the hashes of the nodes that are still to come, the next one last
+    let mut expected: Vec<NodeHash> = vec![header.root_hash.clone()];
+    for idx in 0..header.node_count {
+        let len: [u8; 4] = read_bytes(reader, 4)?.try_into().unwrap();
+        let encoded: Vec<u8> = read_bytes(reader, u32::from_le_bytes(len) as u64)?;
+        hasher.update(len);
+        hasher.update(&encoded);
+        let mut node: Node = bincode::deserialize(&encoded)
+            .map_err(|_| TrieError::InvalidSnapshot("Invalid node record"))?;
+        let node_hash: NodeHash = match &mut node {
+            Node::Root(root) => {
+                root.hash();
+                root.hash.clone()
+            }
+            Node::Branch(branch) => {
+                branch.hash();
+                branch.hash.clone()
+            }
+            Node::Leaf(leaf) => {
+                leaf.hash();
+                leaf.hash.clone()
+            }
+        }
+        .unwrap();
+        match expected.pop() {
+            Some(next) if next == node_hash => {}
+            Some(_) if idx == 0 => {
+                return Err(TrieError::InvalidSnapshot(
+                    "Root hash does not match the header",
+                ))
+            }
+            Some(_) => {
+                return Err(TrieError::InvalidSnapshot(
+                    "Node does not match the trie below the root",
+                ))
+            }
+            None => {
+                return Err(TrieError::InvalidSnapshot(
+                    "Snapshot contains unreachable nodes",
+                ))
+            }
+        }
+        let (left, right) = match &node {
+            Node::Root(root) if idx == 0 => (root.left.clone(), root.right.clone()),
+            Node::Branch(branch) if idx > 0 => (branch.left.clone(), branch.right.clone()),
+            Node::Leaf(_) if idx > 0 => (None, None),
+            _ if idx == 0 => {
+                return Err(TrieError::InvalidSnapshot(
+                    "Root hash does not match the header",
+                ))
+            }
+            _ => return Err(TrieError::InvalidChild),
+        };
+        expected.extend(right);
+        expected.extend(left);
+        f(node_hash, node)?;
+    }
+    if reader.read(&mut [0u8; 1])? != 0 {
+        return Err(TrieError::InvalidSnapshot(
+            "Unexpected data after the nodes",
+        ));
+    }
+    if hasher.finalize().to_vec() != header.checksum {
+        return Err(TrieError::InvalidSnapshot("Checksum does not match"));
+    }
+    if !expected.is_empty() {
+        return Err(TrieError::InvalidSnapshot("Snapshot is missing a node"));
+    }
+    Ok(())
+}
+
+// read and verify a snapshot and write its nodes into db, nothing is written
+// if the snapshot is invalid. Backends with transactions write the nodes in
+// one transaction while they are read, the others buffer the whole snapshot.
+pub fn import_snapshot(db: &mut dyn Database, reader: &mut dyn Read) -> Result<SnapshotHeader> {
+    let header: SnapshotHeader = read_header(reader)?;
+    if !db.supports_transactions() {
+        let mut nodes: Vec<(NodeHash, Node)> = Vec::new();
+        read_nodes(reader, &header, |node_hash, node| {
+            nodes.push((node_hash, node));
+            Ok(())
+        })?;
+        for (node_hash, node) in nodes.into_iter().rev() {
+            db.insert(&node_hash, node)?;
+        }
+        return Ok(header);
+    }
+    db.begin()?;
+    match read_nodes(reader, &header, |node_hash, node| {
+        db.insert(&node_hash, node)
+    }) {
+        Ok(()) => {
+            db.commit()?;
+            Ok(header)
+        }
+        Err(e) => {
+            db.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_snapshot, import_snapshot};
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, TempPath};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB, Database};
+    use crate::store::types::{Leaf, RootHash};
+    use crate::trie::Trie;
+    use std::fs::File;
+
+    #[test]
+    fn test_snapshot() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let mut old_root: RootHash = trie.root_hash();
+        for idx in 0..64 {
+            if idx == 32 {
+                old_root = trie.root_hash();
+            }
+            trie.insert(generate_random_key(), generate_random_data())
+                .unwrap();
+        }
+        let root_hash: RootHash = trie.root_hash();
+        let path = TempPath::new("snapshot.bin");
+        let header = export_snapshot(
+            &mut trie.db,
+            &root_hash,
+            &mut File::create(&path.0).unwrap(),
+        )
+        .unwrap();
+        // the root, 64 leaves and 62 branches, nodes of older roots are not
+        // exported
+        assert_eq!(header.node_count as usize, 2 * 64 - 1);
+        assert!(trie.db.nodes.len() > 2 * 64);
+
+        let db_path = TempPath::new("snapshot.sqlite");
+        let mut db = TrieDB::open(db_path.to_str()).unwrap();
+        assert_eq!(
+            import_snapshot(&mut db, &mut File::open(&path.0).unwrap()).unwrap(),
+            header
+        );
+        let mut imported = Trie::open(db, &root_hash).unwrap();
+        let leaves: Vec<Leaf> = imported.leaves().map(|leaf| leaf.unwrap()).collect();
+        assert_eq!(
+            leaves,
+            trie.leaves()
+                .map(|leaf| leaf.unwrap())
+                .collect::<Vec<Leaf>>()
+        );
+        assert!(Trie::open(imported.db, &old_root).is_err());
+
+        // a changed, malformed, truncated or extended file is rejected before
+        // any write
+        let bytes: Vec<u8> = std::fs::read(&path.0).unwrap();
+        let invalid_path = TempPath::new("snapshot_invalid.sqlite");
+        let mut changed: Vec<u8> = bytes.clone();
+        let last: usize = changed.len() - 1;
+        changed[last] ^= 1;
+        // the variant of the first node, after the magic, the version, the
+        // header and the length of the record
+        let mut malformed: Vec<u8> = bytes.clone();
+        malformed[12 + bincode::serialized_size(&header).unwrap() as usize + 4] = 0xff;
+        let mut extended: Vec<u8> = bytes.clone();
+        extended.push(0);
+        let truncated: Vec<u8> = bytes[..bytes.len() - 1].to_vec();
+        for invalid in [changed, malformed, truncated, extended] {
+            let mut db = InMemoryDB::new();
+            assert!(matches!(
+                import_snapshot(&mut db, &mut invalid.as_slice()),
+                Err(TrieError::InvalidSnapshot(_))
+            ));
+            assert!(db.nodes.is_empty());
+            // the streamed nodes are rolled back
+            let mut db = TrieDB::open(invalid_path.to_str()).unwrap();
+            assert!(matches!(
+                import_snapshot(&mut db, &mut invalid.as_slice()),
+                Err(TrieError::InvalidSnapshot(_))
+            ));
+            assert!(db.node_keys(None, 1).unwrap().is_empty());
+        }
+    }
+}
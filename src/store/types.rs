@@ -1,13 +1,109 @@
 use super::db::Database;
-use anyhow::{bail, Result};
+use crate::error::{Result, TrieError};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 pub type RootHash = Vec<u8>;
 pub type NodeHash = Vec<u8>;
-pub type Key = Vec<u8>;
 pub type Data = Vec<u8>;
 
+// The path to a leaf, 256 bits where bit 0 is the most significant bit of
+// the first byte so that keys sort in the same order as the trie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TrieKey([u8; 32]);
+
+impl TrieKey {
+    pub const BITS: usize = 256;
+
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        match <[u8; 32]>::try_from(bytes) {
+            Ok(bytes) => Ok(Self(bytes)),
+            Err(_) => Err(TrieError::InvalidKey("a key must have 32 bytes")),
+        }
+    }
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(TrieError::InvalidKey("a key must have 64 hex digits"));
+        }
+        let mut bytes = [0u8; 32];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16)
+                .map_err(|_| TrieError::InvalidKey("a key must have 64 hex digits"))?;
+        }
+        Ok(Self(bytes))
+    }
+    // a key given as 256 binary digits
+    pub fn from_bits(bits: &[u8]) -> Result<Self> {
+        if bits.len() != Self::BITS {
+            return Err(TrieError::InvalidKey("a key must have 256 digits"));
+        }
+        let mut bytes = [0u8; 32];
+        for (idx, bit) in bits.iter().enumerate() {
+            match bit {
+                0 => {}
+                1 => bytes[idx / 8] |= 0x80 >> (idx % 8),
+                _ => return Err(TrieError::InvalidKey("a key digit must be 0 or 1")),
+            }
+        }
+        Ok(Self(bytes))
+    }
+    // the key at the path derived from arbitrary data
+    pub fn sha256<T: AsRef<[u8]>>(data: T) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&default_hash(data));
+        Self(bytes)
+    }
+    // the digit of the path at idx, 0 or 1
+    pub fn bit(&self, idx: usize) -> u8 {
+        (self.0[idx / 8] >> (7 - idx % 8)) & 1
+    }
+    // the first index at which the two paths are not equal
+    pub fn first_diff(&self, other: &TrieKey) -> Option<usize> {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .position(|(a, b)| a != b)
+            .map(|byte_idx| {
+                byte_idx * 8 + (self.0[byte_idx] ^ other.0[byte_idx]).leading_zeros() as usize
+            })
+    }
+    // the key with the digit at idx set to bit
+    pub fn with_bit(&self, idx: usize, bit: u8) -> Self {
+        let mut bytes: [u8; 32] = self.0;
+        if bit == 0 {
+            bytes[idx / 8] &= !(0x80 >> (idx % 8));
+        } else {
+            bytes[idx / 8] |= 0x80 >> (idx % 8);
+        }
+        Self(bytes)
+    }
+    // the lowest and highest key that begin with the first bits digits of the key
+    pub fn prefix_bounds(&self, bits: usize) -> (Self, Self) {
+        let (mut lower, mut upper) = (*self, *self);
+        for idx in bits..Self::BITS {
+            lower = lower.with_bit(idx, 0);
+            upper = upper.with_bit(idx, 1);
+        }
+        (lower, upper)
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+impl From<[u8; 32]> for TrieKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Node {
     Root(Root),
@@ -19,19 +115,19 @@ impl Node {
     pub fn unwrap_as_root(self) -> Result<Root> {
         match self {
             Node::Root(root) => Ok(root),
-            _ => bail!("Failed to unwrap as Root"),
+            _ => Err(TrieError::UnexpectedNode("Root")),
         }
     }
     pub fn unwrap_as_branch(self) -> Result<Branch> {
         match self {
             Node::Branch(branch) => Ok(branch),
-            _ => bail!("Failed to unwrap as Branch"),
+            _ => Err(TrieError::UnexpectedNode("Branch")),
         }
     }
     pub fn unwrap_as_leaf(self) -> Result<Leaf> {
         match self {
             Node::Leaf(leaf) => Ok(leaf),
-            _ => bail!("Failed to unwrap as Leaf"),
+            _ => Err(TrieError::UnexpectedNode("Leaf")),
         }
     }
 }
@@ -51,32 +147,30 @@ impl Root {
             right: None,
         }
     }
-    pub fn store(&self, db: &mut dyn Database) {
-        db.insert(
-            &self
-                .hash
-                .clone()
-                .expect("Must compute hash before storing a node, try calling .hash()"),
-            Node::Root(self.clone()),
-        )
+    pub fn store(&self, db: &mut dyn Database) -> Result<()> {
+        match &self.hash {
+            Some(hash) => db.insert(hash, Node::Root(self.clone())),
+            None => Err(TrieError::UnhashedNode),
+        }
     }
-    pub fn hash_and_store(&mut self, db: &mut dyn Database) {
+    pub fn hash_and_store(&mut self, db: &mut dyn Database) -> Result<()> {
         self.hash = None;
         self.hash();
-        self.store(db);
+        self.store(db)
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Branch {
-    pub key: Key,
+    // the split index of the branch
+    pub key: Vec<u8>,
     pub hash: Option<NodeHash>,
     pub left: Option<NodeHash>,
     pub right: Option<NodeHash>,
 }
 
 impl Branch {
-    pub fn empty(key: Key) -> Self {
+    pub fn empty(key: Vec<u8>) -> Self {
         Self {
             key,
             hash: None,
@@ -84,7 +178,7 @@ impl Branch {
             right: None,
         }
     }
-    pub fn new(key: Key, left: Option<NodeHash>, right: Option<NodeHash>) -> Self {
+    pub fn new(key: Vec<u8>, left: Option<NodeHash>, right: Option<NodeHash>) -> Self {
         Self {
             key,
             hash: None,
@@ -92,19 +186,16 @@ impl Branch {
             right,
         }
     }
-    pub fn store(&self, db: &mut dyn Database) {
-        db.insert(
-            &self
-                .hash
-                .clone()
-                .expect("Must compute hash before storing a node, try calling .hash()"),
-            Node::Branch(self.clone()),
-        )
+    pub fn store(&self, db: &mut dyn Database) -> Result<()> {
+        match &self.hash {
+            Some(hash) => db.insert(hash, Node::Branch(self.clone())),
+            None => Err(TrieError::UnhashedNode),
+        }
     }
-    pub fn hash_and_store(&mut self, db: &mut dyn Database) {
+    pub fn hash_and_store(&mut self, db: &mut dyn Database) -> Result<()> {
         self.hash = None;
         self.hash();
-        self.store(db);
+        self.store(db)
     }
     pub fn update(&mut self, left: Option<NodeHash>, right: Option<NodeHash>) {
         self.left = left;
@@ -114,14 +205,14 @@ impl Branch {
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Leaf {
-    pub prefix: Option<Key>,
-    pub key: Key,
+    pub prefix: Option<TrieKey>,
+    pub key: TrieKey,
     pub hash: Option<NodeHash>,
     pub data: Option<Data>,
 }
 
 impl Leaf {
-    pub fn empty(key: Key) -> Self {
+    pub fn empty(key: TrieKey) -> Self {
         Self {
             prefix: None,
             key,
@@ -129,7 +220,7 @@ impl Leaf {
             data: None,
         }
     }
-    pub fn new(key: Key, data: Option<Data>) -> Self {
+    pub fn new(key: TrieKey, data: Option<Data>) -> Self {
         Self {
             prefix: None,
             key,
@@ -137,19 +228,16 @@ impl Leaf {
             data,
         }
     }
-    pub fn hash_and_store(&mut self, db: &mut dyn Database) {
+    pub fn hash_and_store(&mut self, db: &mut dyn Database) -> Result<()> {
         self.hash = None;
         self.hash();
-        self.store(db);
+        self.store(db)
     }
-    pub fn store(&self, db: &mut dyn Database) {
-        db.insert(
-            &self
-                .hash
-                .clone()
-                .expect("Must compute hash before storing a node, try calling .hash()"),
-            Node::Leaf(self.clone()),
-        )
+    pub fn store(&self, db: &mut dyn Database) -> Result<()> {
+        match &self.hash {
+            Some(hash) => db.insert(hash, Node::Leaf(self.clone())),
+            None => Err(TrieError::UnhashedNode),
+        }
     }
 }
 
@@ -183,3 +271,45 @@ pub fn default_hash<T: AsRef<[u8]>>(data: T) -> NodeHash {
     hasher.update(data);
     hasher.finalize().to_vec()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TrieKey;
+
+    #[test]
+    fn test_trie_key() {
+        let mut bits: Vec<u8> = vec![0u8; 256];
+        bits[0] = 1;
+        bits[9] = 1;
+        bits[255] = 1;
+        let key = TrieKey::from_bits(&bits).unwrap();
+        assert_eq!(key.as_bytes()[0], 0x80);
+        assert_eq!(key.as_bytes()[1], 0x40);
+        assert_eq!(key.as_bytes()[31], 0x01);
+        assert!((0..256).all(|idx| key.bit(idx) == bits[idx]));
+        assert_eq!(TrieKey::from_hex(&key.to_hex()).unwrap(), key);
+        assert_eq!(
+            TrieKey::from_hex(&format!("0x{}", key.to_hex())).unwrap(),
+            key
+        );
+        assert_eq!(TrieKey::from_slice(key.as_bytes()).unwrap(), key);
+
+        bits[9] = 0;
+        let other = TrieKey::from_bits(&bits).unwrap();
+        assert_eq!(key.first_diff(&key), None);
+        assert!(other < key);
+        assert_eq!(key.with_bit(9, 0), other);
+        let (lower, upper) = key.prefix_bounds(10);
+        assert_eq!(lower.as_bytes()[..2], [0x80, 0x40]);
+        assert_eq!(upper.as_bytes()[1], 0x7f);
+        assert_eq!(upper.as_bytes()[2..], [0xff; 30]);
+
+        // invalid keys are rejected instead of panicking
+        bits[3] = 7;
+        assert!(TrieKey::from_bits(&bits).is_err());
+        assert!(TrieKey::from_bits(&[0u8; 255]).is_err());
+        assert!(TrieKey::from_slice(&[0u8; 31]).is_err());
+        assert!(TrieKey::from_hex("ff").is_err());
+        assert!(TrieKey::from_hex(&"g".repeat(64)).is_err());
+    }
+}
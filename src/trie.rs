@@ -0,0 +1,240 @@
+// A handle that owns the database and the current root so that callers don't
+// have to thread a Node::Root through every call. Writes are grouped in a
+// transaction that is opened by the first write and closed by commit().
+use crate::error::{Result, TrieError};
+use crate::iter::{leaves, Leaves};
+use crate::merkle::{
+    exclusion_proof, merkle_proof, multi_proof, range_proof, ExclusionProof, MerkleProof,
+    MultiProof, RangeProof,
+};
+use crate::store::{
+    db::Database,
+    refs::RefStore,
+    registry::{RootRecord, RootRegistry},
+    types::{Data, Hashable, Leaf, Node, Root, RootHash, TrieKey},
+};
+use crate::{get_leaf, insert_batch, insert_leaf, remove_leaf, update_leaf};
+
+pub struct Trie<D: Database> {
+    pub db: D,
+    root: Root,
+    // the root of the last commit, restored by rollback()
+    committed_root: Root,
+    pending: bool,
+}
+
+impl<D: Database> Trie<D> {
+    // an empty trie, the empty root is stored so that it can be opened later
+    pub fn new(mut db: D) -> Result<Self> {
+        let mut root: Root = Root::empty();
+        root.hash_and_store(&mut db)?;
+        db.commit_root(root.hash.as_ref().unwrap())?;
+        Ok(Self::at(db, root))
+    }
+    // open the trie at any root that was stored in the database
+    pub fn open(mut db: D, root_hash: &[u8]) -> Result<Self> {
+        let root: Root = db.get_node(root_hash)?.unwrap_as_root()?;
+        Ok(Self::at(db, root))
+    }
+    fn at(db: D, root: Root) -> Self {
+        Self {
+            db,
+            committed_root: root.clone(),
+            root,
+            pending: false,
+        }
+    }
+    pub fn root(&self) -> &Root {
+        &self.root
+    }
+    pub fn root_hash(&self) -> RootHash {
+        self.root.hash.clone().unwrap()
+    }
+    pub fn get_leaf(&mut self, key: &TrieKey) -> Result<Option<Leaf>> {
+        get_leaf(&mut self.db, key, Node::Root(self.root.clone()))
+    }
+    pub fn get(&mut self, key: &TrieKey) -> Result<Option<Data>> {
+        Ok(self.get_leaf(key)?.and_then(|leaf| leaf.data))
+    }
+    pub fn insert(&mut self, key: TrieKey, data: Data) -> Result<RootHash> {
+        let mut leaf: Leaf = Leaf::new(key, Some(data));
+        leaf.hash();
+        self.write(|db, root| insert_leaf(db, &mut leaf, root))
+    }
+    // if a key appears more than once the last entry wins
+    pub fn insert_batch(&mut self, entries: Vec<(TrieKey, Data)>) -> Result<RootHash> {
+        let leafs: Vec<Leaf> = entries
+            .into_iter()
+            .map(|(key, data)| Leaf::new(key, Some(data)))
+            .collect();
+        self.write(|db, root| insert_batch(db, leafs, root))
+    }
+    pub fn update(&mut self, key: TrieKey, data: Data) -> Result<RootHash> {
+        self.write(|db, root| update_leaf(db, key, Some(data), root))
+    }
+    pub fn remove(&mut self, key: TrieKey) -> Result<RootHash> {
+        self.write(|db, root| remove_leaf(db, key, root))
+    }
+    // all leafs ordered by key
+    pub fn leaves(&mut self) -> Leaves<'_> {
+        leaves(&mut self.db, Node::Root(self.root.clone()))
+    }
+    pub fn prove(&mut self, key: TrieKey) -> Result<MerkleProof> {
+        merkle_proof(&mut self.db, key, Node::Root(self.root.clone()))
+    }
+    // one proof for many present or absent keys
+    pub fn prove_many(&mut self, keys: Vec<TrieKey>) -> Result<MultiProof> {
+        multi_proof(&mut self.db, keys, Node::Root(self.root.clone()))
+    }
+    // prove that the proof holds every leaf with start <= key <= end
+    pub fn prove_range(&mut self, start: TrieKey, end: TrieKey) -> Result<RangeProof> {
+        range_proof(&mut self.db, start, end, Node::Root(self.root.clone()))
+    }
+    pub fn prove_absence(&mut self, key: TrieKey) -> Result<ExclusionProof> {
+        exclusion_proof(&mut self.db, key, Node::Root(self.root.clone()))
+    }
+    // store all writes since the last commit and return the new root hash
+    pub fn commit(&mut self) -> Result<RootHash> {
+        if self.pending {
+            self.db.commit_root(&self.root_hash())?;
+            self.db.commit()?;
+            self.pending = false;
+        }
+        self.committed_root = self.root.clone();
+        Ok(self.root_hash())
+    }
+    // discard all writes since the last commit. Backends without
+    // transactions keep the written nodes, but the trie returns to the
+    // committed root.
+    pub fn rollback(&mut self) -> Result<()> {
+        if self.pending {
+            self.db.rollback()?;
+            self.pending = false;
+        }
+        self.root = self.committed_root.clone();
+        Ok(())
+    }
+    // run a write inside the open transaction and move to the new root
+    fn write(
+        &mut self,
+        f: impl FnOnce(&mut dyn Database, Node) -> Result<Root>,
+    ) -> Result<RootHash> {
+        if !self.pending {
+            self.db.begin()?;
+            self.pending = true;
+        }
+        self.root = f(&mut self.db, Node::Root(self.root.clone()))?;
+        Ok(self.root_hash())
+    }
+}
+
+impl<D: RootRegistry> Trie<D> {
+    // open the trie at the root that was registered at version
+    pub fn open_version(mut db: D, version: u64) -> Result<Self> {
+        let Some(record) = db.root_at(version)? else {
+            return Err(TrieError::MissingVersion(version));
+        };
+        Self::open(db, &record.root_hash)
+    }
+    // register the current root at version and commit, the record is
+    // written in the same transaction as the pending writes
+    pub fn commit_version(&mut self, version: u64, metadata: &[u8]) -> Result<RootRecord> {
+        if !self.pending {
+            self.db.begin()?;
+            self.pending = true;
+        }
+        let root_hash: RootHash = self.root_hash();
+        let record: RootRecord = self.db.register_root(version, &root_hash, metadata)?;
+        self.commit()?;
+        Ok(record)
+    }
+}
+
+impl<D: RefStore> Trie<D> {
+    // open the trie at the root that name points at
+    pub fn open_ref(mut db: D, name: &str) -> Result<Self> {
+        let Some(root_hash) = db.get_ref(name)? else {
+            return Err(TrieError::MissingRef(name.to_string()));
+        };
+        Self::open(db, &root_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trie;
+    use crate::error::TrieError;
+    use crate::merkle::tests::{generate_random_data, generate_random_key, TempPath};
+    use crate::merkle::{verify_exclusion_proof, verify_merkle_proof_for_key};
+    use crate::store::db::{memory::InMemoryDB, sql::TrieDB};
+    use crate::store::types::TrieKey;
+
+    #[test]
+    fn test_trie() {
+        let mut trie = Trie::new(InMemoryDB::new()).unwrap();
+        let empty_root = trie.root_hash();
+        let keys: Vec<TrieKey> = (0..16).map(|_| generate_random_key()).collect();
+        for key in &keys {
+            trie.insert(*key, generate_random_data()).unwrap();
+        }
+        let old_root = trie.commit().unwrap();
+        let data = generate_random_data();
+        trie.update(keys[3], data.clone()).unwrap();
+        assert_eq!(trie.get(&keys[3]).unwrap(), Some(data.clone()));
+        assert!(matches!(
+            trie.insert(keys[3], data.clone()),
+            Err(TrieError::DuplicateLeaf)
+        ));
+
+        let proof = trie.prove(keys[3]).unwrap();
+        assert!(
+            verify_merkle_proof_for_key(proof.nodes, &keys[3], Some(&data), trie.root_hash())
+                .unwrap()
+        );
+        trie.remove(keys[3]).unwrap();
+        assert_eq!(trie.get(&keys[3]).unwrap(), None);
+        let proof = trie.prove_absence(keys[3]).unwrap();
+        assert!(verify_exclusion_proof(proof.nodes, keys[3], trie.root_hash()).unwrap());
+        let new_root = trie.commit().unwrap();
+
+        // older roots can still be opened
+        let mut old_trie = Trie::open(trie.db, &old_root).unwrap();
+        assert!(old_trie.get(&keys[3]).unwrap().is_some());
+        let mut trie = Trie::open(old_trie.db, &new_root).unwrap();
+        assert_eq!(trie.get(&keys[3]).unwrap(), None);
+        assert!(Trie::open(InMemoryDB::new(), &new_root).is_err());
+
+        // a batch gives the same root as single inserts
+        let mut batch_trie = Trie::new(InMemoryDB::new()).unwrap();
+        let entries = keys
+            .iter()
+            .filter(|key| **key != keys[3])
+            .map(|key| (*key, trie.get(key).unwrap().unwrap()))
+            .collect();
+        assert_eq!(batch_trie.insert_batch(entries).unwrap(), new_root);
+
+        for key in &keys {
+            if *key != keys[3] {
+                trie.remove(*key).unwrap();
+            }
+        }
+        assert_eq!(trie.root_hash(), empty_root);
+    }
+
+    #[test]
+    fn test_trie_rollback() {
+        let path = TempPath::new("trie_rollback.sqlite");
+        let mut trie = Trie::new(TrieDB::open(path.to_str()).unwrap()).unwrap();
+        let key = generate_random_key();
+        trie.insert(key, generate_random_data()).unwrap();
+        let committed_root = trie.commit().unwrap();
+
+        let other_key = generate_random_key();
+        let discarded_root = trie.insert(other_key, generate_random_data()).unwrap();
+        trie.rollback().unwrap();
+        assert_eq!(trie.root_hash(), committed_root);
+        assert_eq!(trie.get(&other_key).unwrap(), None);
+        // the nodes of the discarded root were never stored
+        assert!(Trie::open(trie.db, &discarded_root).is_err());
+    }
+}